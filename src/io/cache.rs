@@ -0,0 +1,351 @@
+use std::convert::TryInto;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::mem::size_of;
+use std::path::PathBuf;
+
+use super::super::math::aabbox::AABBox3;
+use super::super::math::bvh::{get_depth, Node, STACK_SIZE};
+use super::super::math::vector::{Axis, Vec3};
+use super::super::math::Real;
+
+const MAGIC: &[u8; 8] = b"DUSTBVH\0";
+/// Must change whenever the file layout or the trees the builder makes do,
+/// so older files are rebuilt instead of loaded.
+const VERSION: u32 = 2;
+/// Environment variable that moves the default cache directory.
+pub const DIRECTORY_VARIABLE: &str = "DUST_CACHE_DIR";
+
+/// 64 bit FNV-1a, unlike the standard hasher it gives the same value on
+/// every run and platform, so it can name files.
+pub struct ContentHash {
+    state: u64,
+}
+
+impl ContentHash {
+    pub fn new() -> ContentHash {
+        ContentHash {
+            state: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.state ^= *b as u64;
+            self.state = self.state.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn write_u64(&mut self, v: u64) {
+        self.write(&v.to_le_bytes());
+    }
+
+    pub fn write_real(&mut self, v: Real) {
+        self.write(&v.to_le_bytes());
+    }
+
+    pub fn write_vec3(&mut self, v: &Vec3) {
+        self.write_real(v.x);
+        self.write_real(v.y);
+        self.write_real(v.z);
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+impl Default for ContentHash {
+    fn default() -> Self {
+        ContentHash::new()
+    }
+}
+
+/// A directory of built acceleration structures named by the content hash
+/// of what they were built over, so loading the same mesh again skips the
+/// build.
+pub struct Cache {
+    directory: PathBuf,
+}
+
+impl Cache {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Cache {
+        Cache {
+            directory: directory.into(),
+        }
+    }
+
+    /// The directory `DUST_CACHE_DIR` names, otherwise `dust-cache` in the
+    /// temporary directory of the system.
+    pub fn get_default_directory() -> PathBuf {
+        match env::var_os(DIRECTORY_VARIABLE) {
+            Some(ref directory) if !directory.is_empty() => PathBuf::from(directory),
+            _ => env::temp_dir().join("dust-cache"),
+        }
+    }
+
+    pub fn get_directory(&self) -> &PathBuf {
+        &self.directory
+    }
+
+    fn get_path(&self, hash: u64) -> PathBuf {
+        self.directory.join(format!("{:016x}.bvh", hash))
+    }
+
+    /// The nodes and object order of the tree saved under `hash` for
+    /// `objects_count` objects. None when there is no usable file, because
+    /// it is missing, damaged or written by another version or precision.
+    pub fn load_tree(&self, hash: u64, objects_count: usize) -> Option<(Vec<Node>, Vec<usize>)> {
+        let mut data = Vec::new();
+        File::open(self.get_path(hash))
+            .ok()?
+            .read_to_end(&mut data)
+            .ok()?;
+        if data.len() < 8 {
+            return None;
+        }
+        let (payload, checksum) = data.split_at(data.len() - 8);
+        let mut h = ContentHash::new();
+        h.write(payload);
+        if h.finish().to_le_bytes() != checksum {
+            return None;
+        }
+        let mut r = Reader { data: payload };
+        if r.take(MAGIC.len())? != MAGIC
+            || r.u32()? != VERSION
+            || r.u32()? != size_of::<Real>() as u32
+            || r.u64()? != hash
+            || r.u64()? != objects_count as u64
+        {
+            return None;
+        }
+        let nodes_count = r.u64()? as usize;
+        let mut nodes = Vec::with_capacity(nodes_count.min(payload.len()));
+        for _ in 0..nodes_count {
+            nodes.push(Node {
+                bbox: AABBox3 {
+                    blf: r.vec3()?,
+                    trr: r.vec3()?,
+                },
+                offset: r.u64()? as usize,
+                count: r.u64()? as usize,
+                axis: *Axis::ALL.get(r.u8()? as usize)?,
+            });
+        }
+        let order_count = r.u64()? as usize;
+        let mut order = Vec::with_capacity(order_count.min(payload.len()));
+        let mut ordered = vec![false; objects_count];
+        for _ in 0..order_count {
+            let i = r.u64()? as usize;
+            if i >= objects_count || ordered[i] {
+                return None;
+            }
+            ordered[i] = true;
+            order.push(i);
+        }
+        for (i, n) in nodes.iter().enumerate() {
+            let valid = if n.is_leaf() {
+                n.offset
+                    .checked_add(n.count)
+                    .is_some_and(|end| end <= order.len())
+            } else {
+                n.offset > i + 1 && n.offset < nodes.len()
+            };
+            if !valid {
+                return None;
+            }
+        }
+        if get_depth(&nodes) > STACK_SIZE {
+            return None;
+        }
+        Some((nodes, order))
+    }
+
+    /// Failing to save only costs the next load a rebuild, so errors are
+    /// reported and otherwise ignored.
+    pub fn store_tree(&self, hash: u64, nodes: &[Node], order: &[usize], objects_count: usize) {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.extend_from_slice(&(size_of::<Real>() as u32).to_le_bytes());
+        data.extend_from_slice(&hash.to_le_bytes());
+        data.extend_from_slice(&(objects_count as u64).to_le_bytes());
+        data.extend_from_slice(&(nodes.len() as u64).to_le_bytes());
+        for n in nodes {
+            for v in [&n.bbox.blf, &n.bbox.trr].iter() {
+                data.extend_from_slice(&v.x.to_le_bytes());
+                data.extend_from_slice(&v.y.to_le_bytes());
+                data.extend_from_slice(&v.z.to_le_bytes());
+            }
+            data.extend_from_slice(&(n.offset as u64).to_le_bytes());
+            data.extend_from_slice(&(n.count as u64).to_le_bytes());
+            data.push(n.axis as u8);
+        }
+        data.extend_from_slice(&(order.len() as u64).to_le_bytes());
+        for i in order {
+            data.extend_from_slice(&(*i as u64).to_le_bytes());
+        }
+        let mut h = ContentHash::new();
+        h.write(&data);
+        data.extend_from_slice(&h.finish().to_le_bytes());
+        // Renaming a finished file keeps a concurrent load from reading a
+        // partly written one.
+        let path = self.get_path(hash);
+        let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+        let result = fs::create_dir_all(&self.directory)
+            .and_then(|_| File::create(&temporary)?.write_all(&data))
+            .and_then(|_| fs::rename(&temporary, &path));
+        if let Err(e) = result {
+            println!("Unable to cache the tree in {:?}: {:?}", path, e);
+            let _ = fs::remove_file(&temporary);
+        }
+    }
+}
+
+/// Reads little endian values from the front of a byte slice.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.data.len() < n {
+            return None;
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn real(&mut self) -> Option<Real> {
+        Some(Real::from_le_bytes(
+            self.take(size_of::<Real>())?.try_into().ok()?,
+        ))
+    }
+
+    fn vec3(&mut self) -> Option<Vec3> {
+        Some(Vec3 {
+            x: self.real()?,
+            y: self.real()?,
+            z: self.real()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cache in its own temporary directory, removed when dropped.
+    struct TemporaryCache(Cache);
+
+    impl TemporaryCache {
+        fn new(name: &str) -> TemporaryCache {
+            let directory =
+                std::env::temp_dir().join(format!("dust-cache-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&directory);
+            TemporaryCache(Cache::new(directory))
+        }
+    }
+
+    impl Drop for TemporaryCache {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0.directory);
+        }
+    }
+
+    fn node(offset: usize, count: usize, axis: Axis) -> Node {
+        let blf = Vec3 {
+            x: offset as Real,
+            y: -1.5,
+            z: 0.25,
+        };
+        let trr = &blf
+            + &Vec3 {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            };
+        Node {
+            bbox: AABBox3 { blf, trr },
+            offset,
+            count,
+            axis,
+        }
+    }
+
+    /// A root over two leaves of one and two objects.
+    fn tree() -> (Vec<Node>, Vec<usize>) {
+        (
+            vec![
+                node(2, 0, Axis::Y),
+                node(0, 1, Axis::X),
+                node(1, 2, Axis::Z),
+            ],
+            vec![2, 0, 1],
+        )
+    }
+
+    #[test]
+    fn stored_trees_load_back() {
+        let cache = TemporaryCache::new("round-trip");
+        let (nodes, order) = tree();
+        cache.0.store_tree(7, &nodes, &order, 3);
+        let (loaded, loaded_order) = cache.0.load_tree(7, 3).unwrap();
+        assert_eq!(loaded_order, order);
+        assert_eq!(loaded.len(), nodes.len());
+        for (a, b) in loaded.iter().zip(&nodes) {
+            assert!((&a.bbox.blf - &b.bbox.blf).is_zero());
+            assert!((&a.bbox.trr - &b.bbox.trr).is_zero());
+            assert_eq!((a.offset, a.count, a.axis), (b.offset, b.count, b.axis));
+        }
+        assert!(cache.0.load_tree(8, 3).is_none());
+    }
+
+    #[test]
+    fn damaged_files_are_rejected() {
+        let cache = TemporaryCache::new("checksum");
+        let (nodes, order) = tree();
+        cache.0.store_tree(7, &nodes, &order, 3);
+        let path = cache.0.get_path(7);
+        let mut data = fs::read(&path).unwrap();
+        data[MAGIC.len() + 20] ^= 1;
+        fs::write(&path, &data).unwrap();
+        assert!(cache.0.load_tree(7, 3).is_none());
+    }
+
+    #[test]
+    fn trees_of_another_object_count_are_rejected() {
+        let cache = TemporaryCache::new("count");
+        let (nodes, order) = tree();
+        cache.0.store_tree(7, &nodes, &order, 3);
+        assert!(cache.0.load_tree(7, 4).is_none());
+        assert!(cache.0.load_tree(7, 2).is_none());
+    }
+
+    #[test]
+    fn trees_with_bad_node_offsets_are_rejected() {
+        let cache = TemporaryCache::new("offset");
+        let (mut nodes, order) = tree();
+        nodes[0].offset = 5;
+        cache.0.store_tree(7, &nodes, &order, 3);
+        assert!(cache.0.load_tree(7, 3).is_none());
+        let (mut nodes, order) = tree();
+        nodes[2].offset = 2;
+        cache.0.store_tree(7, &nodes, &order, 3);
+        assert!(cache.0.load_tree(7, 3).is_none());
+    }
+}
@@ -1,12 +1,6 @@
 use std::fs::File;
-use std::io::{
-    BufReader,
-    Read,
-};
-use std::mem::{
-    transmute,
-    size_of,
-};
+use std::io::{BufReader, Read};
+use std::mem::{size_of, transmute};
 use std::slice::from_raw_parts_mut;
 
 pub struct Stream {
@@ -15,29 +9,23 @@ pub struct Stream {
 }
 
 impl Stream {
-
     #[cfg(target_endian = "big")]
     fn check_endian(&mut self) {
-        if self.read_bool() {
-            self.not_same_endianness = true;
-        } else {
-            self.not_same_endianness = false;
-        }
+        self.not_same_endianness = self.read_bool();
     }
 
     #[cfg(target_endian = "little")]
     fn check_endian(&mut self) {
-        if self.read_bool() {
-            self.not_same_endianness = false;
-        } else {
-            self.not_same_endianness = true;
-        }
+        self.not_same_endianness = !self.read_bool();
     }
 
     pub fn new(file_name: &String) -> Stream {
         match File::open(file_name) {
             Ok(f) => {
-                let mut s = Stream {not_same_endianness: false, reader: BufReader::new(f)};
+                let mut s = Stream {
+                    not_same_endianness: false,
+                    reader: BufReader::new(f),
+                };
                 s.check_endian();
                 s
             }
@@ -61,14 +49,13 @@ impl Stream {
                 panic!("Error in reading file.");
             }
         }
-        if byte[0] == 0 {
-            false
-        } else {
-            true
-        }
+        byte[0] != 0
     }
 
-    pub fn read<T>(&mut self, default: &T) -> T where T: Sized + Copy {
+    pub fn read<T>(&mut self, default: &T) -> T
+    where
+        T: Sized + Copy,
+    {
         let t_size = size_of::<T>();
         let mut t = *default;
         unsafe {
@@ -88,9 +75,7 @@ impl Stream {
             if self.not_same_endianness {
                 for i in 0..(t_size / 2) {
                     let ci = t_size - (i + 1);
-                    let tmp = (*bytes)[ci];
-                    (*bytes)[ci] = (*bytes)[i];
-                    (*bytes)[i] = tmp;
+                    bytes.swap(ci, i);
                 }
             }
         }
@@ -0,0 +1,8 @@
+//! The dust engine, the binary only drives a small part of it.
+
+extern crate image;
+extern crate num_cpus;
+
+pub mod io;
+pub mod math;
+pub mod render;
@@ -1,8 +1,111 @@
-extern crate rand;
+extern crate dust;
+extern crate image;
 
-mod math;
-mod render;
+use dust::math::bvh::Bvh;
+use dust::math::sphere::Sphere;
+use dust::math::vector::Vec3;
+use dust::math::Real;
+use dust::render::camera::{Base, PerspectiveCamera};
+use dust::render::engine::{CpuEngine, Data};
+use dust::render::hitable_list::HitableList;
+use dust::render::material::{Dielectric, Lambertian, Metal};
+use dust::render::renderer::{Background, Renderer};
+use dust::render::sampler::SobolSampler;
+
+const WIDTH: u32 = 400;
+const HEIGHT: u32 = 200;
 
 fn main() {
-    println!("Work in progress.");
-}
\ No newline at end of file
+    let up = Vec3 {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+    };
+    let camera = PerspectiveCamera::new(Base::new(
+        &Vec3::new(),
+        &Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: -1.0,
+        },
+        &up,
+        WIDTH as Real / HEIGHT as Real,
+    ));
+    let mut world = HitableList::new();
+    world.add(Box::new(Sphere {
+        center: Vec3 {
+            x: 0.0,
+            y: -100.5,
+            z: -2.0,
+        },
+        radius: 100.0,
+        material: Box::new(Lambertian::new(Vec3 {
+            x: 0.8,
+            y: 0.8,
+            z: 0.0,
+        })),
+    }));
+    world.add(Box::new(Sphere {
+        center: Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: -2.0,
+        },
+        radius: 0.5,
+        material: Box::new(Lambertian::new(Vec3 {
+            x: 0.8,
+            y: 0.3,
+            z: 0.3,
+        })),
+    }));
+    world.add(Box::new(Sphere {
+        center: Vec3 {
+            x: 1.0,
+            y: 0.0,
+            z: -2.0,
+        },
+        radius: 0.5,
+        material: Box::new(Metal::new(
+            Vec3 {
+                x: 0.8,
+                y: 0.6,
+                z: 0.2,
+            },
+            0.3,
+        )),
+    }));
+    world.add(Box::new(Sphere {
+        center: Vec3 {
+            x: -1.0,
+            y: 0.0,
+            z: -2.0,
+        },
+        radius: 0.5,
+        material: Box::new(Dielectric::new(1.5)),
+    }));
+    let world = Bvh::new(world.objects, 4);
+    let background = Background::Sky {
+        up,
+        horizon: Vec3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        },
+        zenith: Vec3 {
+            x: 0.5,
+            y: 0.7,
+            z: 1.0,
+        },
+    };
+    let engine = CpuEngine::new(Data {
+        view_port_dimension: (WIDTH, HEIGHT),
+        sampler: Box::new(SobolSampler::new(16)),
+        seed: 0,
+        world: Box::new(world),
+        renderer: Renderer::new(50, background),
+        cameras: vec![Box::new(camera)],
+    });
+    let bitmap = engine.render();
+    image::save_buffer("dust.png", &bitmap, WIDTH, HEIGHT, image::ColorType::Rgba8)
+        .expect("Unable to save the rendered image.");
+}
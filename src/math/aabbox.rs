@@ -1,9 +1,11 @@
-use ::math::vector::{
-    Vec3,
-    Axis,
-};
+use super::float::gamma;
+use super::matrix::Mat4x4;
+use super::ray::Ray3;
+use super::vector::{Axis, Vec3};
+use super::Real;
 
-use ::math::ray::Ray3;
+/// 1 + 2 gamma(3), the bound of the rounding error of the slab distances.
+const SLAB_ROUNDING: Real = 1.0 + 2.0 * gamma(3);
 
 pub trait ExpandableToOther {
     fn expand(&mut self, o: &Self);
@@ -20,63 +22,166 @@ pub struct AABBox3 {
 }
 
 impl ExpandableToOther for AABBox3 {
-    fn expand(&mut self, o : &Self) {
-        if o.blf.x < self.blf.x { self.blf.x = o.blf.x; }
-        if o.blf.y < self.blf.y { self.blf.y = o.blf.y; }
-        if o.blf.z < self.blf.z { self.blf.z = o.blf.z; }
-
-        if o.trr.x > self.trr.x { self.trr.x = o.trr.x; }
-        if o.trr.y > self.trr.y { self.trr.y = o.trr.y; }
-        if o.trr.z > self.trr.z { self.trr.z = o.trr.z; }
+    fn expand(&mut self, o: &Self) {
+        if o.blf.x < self.blf.x {
+            self.blf.x = o.blf.x;
+        }
+        if o.blf.y < self.blf.y {
+            self.blf.y = o.blf.y;
+        }
+        if o.blf.z < self.blf.z {
+            self.blf.z = o.blf.z;
+        }
+
+        if o.trr.x > self.trr.x {
+            self.trr.x = o.trr.x;
+        }
+        if o.trr.y > self.trr.y {
+            self.trr.y = o.trr.y;
+        }
+        if o.trr.z > self.trr.z {
+            self.trr.z = o.trr.z;
+        }
     }
 }
 
 impl ExpandableToPoint3 for AABBox3 {
-    fn expand(&mut self, p : &Vec3) {
-        if p.x < self.blf.x { self.blf.x = p.x; }
-        if p.y < self.blf.y { self.blf.y = p.y; }
-        if p.z < self.blf.z { self.blf.z = p.z; }
+    fn expand(&mut self, p: &Vec3) {
+        if p.x < self.blf.x {
+            self.blf.x = p.x;
+        }
+        if p.y < self.blf.y {
+            self.blf.y = p.y;
+        }
+        if p.z < self.blf.z {
+            self.blf.z = p.z;
+        }
+
+        if p.x > self.trr.x {
+            self.trr.x = p.x;
+        }
+        if p.y > self.trr.y {
+            self.trr.y = p.y;
+        }
+        if p.z > self.trr.z {
+            self.trr.z = p.z;
+        }
     }
 }
 
 impl AABBox3 {
+    /// An empty box, expanding it by anything gives that thing's bounds.
     pub fn new() -> AABBox3 {
         AABBox3 {
-            blf: Vec3::new(),
-            trr: Vec3::new(),
+            blf: Vec3 {
+                x: Real::MAX,
+                y: Real::MAX,
+                z: Real::MAX,
+            },
+            trr: Vec3 {
+                x: -Real::MAX,
+                y: -Real::MAX,
+                z: -Real::MAX,
+            },
         }
     }
 
-    pub fn get_longest_axis(&self) -> Axis {
-        let diff = self.trr - self.blf; // TODO check for occurance, if it is too much store it in box
-        if diff.x > diff.y && diff.x > diff.z { return Axis::X; }
-        if diff.y > diff.x && diff.y > diff.z { return Axis::Y; }
-        return Axis::Z;
+    pub fn is_empty(&self) -> bool {
+        self.blf.x > self.trr.x || self.blf.y > self.trr.y || self.blf.z > self.trr.z
     }
 
-    // Check if ray intersects with box. Returns true/false and stores distance in t
-    pub fn intersection(&self, r: &Ray3) -> (bool, f64) {
+    pub fn get_center(&self) -> Vec3 {
+        &(&self.blf + &self.trr) * 0.5
+    }
 
-        let tx1 = (self.blf.x - r.o.x) * r.invd.x;
-        let tx2 = (self.trr.x - r.o.x) * r.invd.x;
+    pub fn get_diagonal(&self) -> Vec3 {
+        &self.trr - &self.blf
+    }
 
-        let mut tmin = tx1.min(tx2);
-        let mut tmax = tx1.max(tx2);
+    pub fn get_surface_area(&self) -> Real {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.get_diagonal();
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
 
-        let ty1 = (self.blf.y - r.o.y) * r.invd.y;
-        let ty2 = (self.trr.y - r.o.y) * r.invd.y;
+    /// Bounds of the box's eight corners after transforming them by `m`.
+    pub fn transformed(&self, m: &Mat4x4) -> AABBox3 {
+        let mut b = AABBox3::new();
+        if self.is_empty() {
+            return b;
+        }
+        for i in 0..8 {
+            let corner = Vec3 {
+                x: if i & 1 == 0 { self.blf.x } else { self.trr.x },
+                y: if i & 2 == 0 { self.blf.y } else { self.trr.y },
+                z: if i & 4 == 0 { self.blf.z } else { self.trr.z },
+            };
+            ExpandableToPoint3::expand(&mut b, &m.transform_point(&corner));
+        }
+        b
+    }
 
-        tmin = tmin.max(ty1.min(ty2));
-        tmax = tmax.min(ty1.max(ty2));
+    /// Bounds of a circle around `center` in the plane perpendicular to the
+    /// unit vector `normal`.
+    pub fn around_circle(center: &Vec3, normal: &Vec3, radius: Real) -> AABBox3 {
+        let e = Vec3 {
+            x: radius * (1.0 - normal.x * normal.x).max(0.0).sqrt(),
+            y: radius * (1.0 - normal.y * normal.y).max(0.0).sqrt(),
+            z: radius * (1.0 - normal.z * normal.z).max(0.0).sqrt(),
+        };
+        AABBox3 {
+            blf: center - &e,
+            trr: center + &e,
+        }
+    }
 
-        let tz1 = (self.blf.z - r.o.z) * r.invd.z;
-        let tz2 = (self.trr.z - r.o.z) * r.invd.z;
+    pub fn get_longest_axis(&self) -> Axis {
+        let diff = self.get_diagonal();
+        if diff.x > diff.y && diff.x > diff.z {
+            return Axis::X;
+        }
+        if diff.y > diff.x && diff.y > diff.z {
+            return Axis::Y;
+        }
+        Axis::Z
+    }
 
-        tmin = tmin.max(tz1.min(tz2));
-        tmax = tmax.min(tz1.max(tz2));
+    /// The lower corner of the box for `sign` 0 and the upper one for 1.
+    pub fn get_bound(&self, sign: usize) -> &Vec3 {
+        if sign == 0 {
+            &self.blf
+        } else {
+            &self.trr
+        }
+    }
 
-        let t = tmin;
+    /// Slab test, returns the entry and exit distances of the ray clipped to
+    /// (`t_min`, `t_max`). An axis the ray is parallel to and lies exactly on
+    /// the side of produces NaN, which `max` and `min` drop, so such rays
+    /// count as inside the slab instead of poisoning the interval.
+    pub fn intersection(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<(Real, Real)> {
+        let mut tmin = t_min;
+        let mut tmax = t_max;
+        for a in Axis::ALL.iter() {
+            let s = r.get_sign(*a);
+            let t0 = (self.get_bound(s)[*a] - r.o[*a]) * r.invd[*a];
+            // Widening the exit a few ulps keeps rounding from missing boxes
+            // that the ray grazes or that are flat along this axis.
+            let t1 = (self.get_bound(1 - s)[*a] - r.o[*a]) * r.invd[*a] * SLAB_ROUNDING;
+            tmin = t0.max(tmin);
+            tmax = t1.min(tmax);
+            if tmax < tmin {
+                return None;
+            }
+        }
+        Some((tmin, tmax))
+    }
+}
 
-        return (tmax >= tmin, t);
+impl Default for AABBox3 {
+    fn default() -> Self {
+        AABBox3::new()
     }
 }
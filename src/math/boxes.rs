@@ -0,0 +1,274 @@
+use super::super::render::hit::{Hitable, Info as HitInfo};
+use super::super::render::material::Material;
+use super::aabbox::AABBox3;
+use super::float::gamma;
+use super::quaternion::Quaternion;
+use super::ray::Ray3;
+use super::vector::{Axis, Vec2, Vec3};
+use super::Real;
+
+/// Closest face of `b` hit by `r` inside (`t_min`, `t_max`), the entry face
+/// for rays starting outside and the exit face for rays starting inside.
+/// Returns the distance, the point with the bound of its rounding error,
+/// the normal and the uv coordinates of the face.
+fn hit_faces(
+    b: &AABBox3,
+    r: &Ray3,
+    t_min: Real,
+    t_max: Real,
+) -> Option<(Real, (Vec3, Vec3), Vec3, Vec2)> {
+    let mut near = (-Real::MAX, Axis::X, 0);
+    let mut far = (Real::MAX, Axis::X, 0);
+    for a in Axis::ALL.iter() {
+        let s = r.get_sign(*a);
+        // NaN for rays parallel to and on the face planes fails both tests.
+        let t0 = (b.get_bound(s)[*a] - r.o[*a]) * r.invd[*a];
+        let t1 = (b.get_bound(1 - s)[*a] - r.o[*a]) * r.invd[*a];
+        if t0 > near.0 {
+            near = (t0, *a, s);
+        }
+        if t1 < far.0 {
+            far = (t1, *a, 1 - s);
+        }
+    }
+    if near.0 > far.0 {
+        return None;
+    }
+    let (t, axis, side) = if near.0 > t_min && near.0 < t_max {
+        near
+    } else if far.0 > t_min && far.0 < t_max {
+        far
+    } else {
+        return None;
+    };
+    let mut n = Vec3::new();
+    n[axis] = if side == 0 { -1.0 } else { 1.0 };
+    let mut p = r.point_at_parameter(t);
+    let mut p_error = &p.abs() * gamma(3);
+    // On the face axis the point is known exactly.
+    p[axis] = b.get_bound(side)[axis];
+    p_error[axis] = 0.0;
+    let size = b.get_diagonal();
+    let u_axis = Axis::ALL[(axis as usize + 1) % 3];
+    let v_axis = Axis::ALL[(axis as usize + 2) % 3];
+    // A box flat along an axis has faces without extent along it.
+    let coord = |a: Axis| {
+        if size[a] > 0.0 {
+            (p[a] - b.blf[a]) / size[a]
+        } else {
+            0.0
+        }
+    };
+    let uv = Vec2 {
+        x: coord(u_axis),
+        y: coord(v_axis),
+    };
+    Some((t, (p, p_error), n, uv))
+}
+
+/// Axis aligned box, every face gets the uv coordinates [0, 1] x [0, 1].
+pub struct AxisAlignedBox {
+    pub bbox: AABBox3,
+    pub material: Box<dyn Material>,
+}
+
+impl Hitable for AxisAlignedBox {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        let (t, (p, p_error), n, uv) = hit_faces(&self.bbox, r, t_min, t_max)?;
+        Some(HitInfo {
+            t,
+            p,
+            p_error,
+            n,
+            ng: n,
+            uv,
+            m: self.material.as_ref(),
+        })
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        Some(self.bbox)
+    }
+}
+
+/// Box with `half_extents` along its local axes, rotated by `rotation`
+/// around `center`.
+pub struct OrientedBox {
+    pub center: Vec3,
+    pub rotation: Quaternion,
+    pub half_extents: Vec3,
+    pub material: Box<dyn Material>,
+}
+
+impl Hitable for OrientedBox {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        let inverse = self.rotation.conjugate();
+        let local_ray = Ray3::new(inverse.rotate(&(&r.o - &self.center)), inverse.rotate(&r.d));
+        let local_box = AABBox3 {
+            blf: -&self.half_extents,
+            trr: self.half_extents,
+        };
+        // Rotation keeps the distances along the ray.
+        let (t, (local_p, local_error), n, uv) = hit_faces(&local_box, &local_ray, t_min, t_max)?;
+        let rotated = self.rotation.rotate(&local_p);
+        let p = &self.center + &rotated;
+        // Rotating mixes the axes, so every axis gets the whole error.
+        let spread = gamma(16) * local_p.length() + local_error.length();
+        let p_error = &Vec3 {
+            x: spread,
+            y: spread,
+            z: spread,
+        } + &(&(&self.center.abs() + &rotated.abs()) * gamma(1));
+        let n = self.rotation.rotate(&n);
+        Some(HitInfo {
+            t,
+            p,
+            p_error,
+            n,
+            ng: n,
+            uv,
+            m: self.material.as_ref(),
+        })
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        let mut e = Vec3::new();
+        for a in Axis::ALL.iter() {
+            let mut axis = Vec3::new();
+            axis[*a] = self.half_extents[*a];
+            let axis = self.rotation.rotate(&axis);
+            e.x += axis.x.abs();
+            e.y += axis.y.abs();
+            e.z += axis.z.abs();
+        }
+        Some(AABBox3 {
+            blf: &self.center - &e,
+            trr: &self.center + &e,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::render::material::Lambertian;
+    use super::*;
+
+    fn oriented_box() -> OrientedBox {
+        OrientedBox {
+            center: Vec3 {
+                x: 1.0,
+                y: -0.5,
+                z: 2.0,
+            },
+            rotation: Quaternion::from_axis_angle(
+                0.7,
+                &Vec3 {
+                    x: 1.0,
+                    y: 2.0,
+                    z: -0.5,
+                }
+                .normalized(),
+            ),
+            half_extents: Vec3 {
+                x: 0.5,
+                y: 1.0,
+                z: 0.25,
+            },
+            material: Box::new(Lambertian::new(Vec3::new())),
+        }
+    }
+
+    /// The axis of `b`'s space the point `p` lies farthest out along,
+    /// relative to the extents, and how far that is.
+    fn farthest_axis(b: &OrientedBox, p: &Vec3) -> (Vec3, Real) {
+        let local = b.rotation.conjugate().rotate(&(p - &b.center));
+        let mut best = (Vec3::new(), 0.0);
+        for a in Axis::ALL.iter() {
+            let out = local[*a] / b.half_extents[*a];
+            if out.abs() > best.1 {
+                let mut n = Vec3::new();
+                n[*a] = out.signum();
+                best = (b.rotation.rotate(&n), out.abs());
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn oriented_boxes_are_hit_on_their_faces() {
+        let b = oriented_box();
+        for i in 0..8 {
+            for j in 0..8 {
+                let (phi, theta) = (i as Real * 0.8, (j as Real + 0.5) * 0.39);
+                let dir = Vec3 {
+                    x: theta.sin() * phi.cos(),
+                    y: theta.cos(),
+                    z: theta.sin() * phi.sin(),
+                };
+                let o = &b.center + &(&dir * 4.0);
+                let target = &b.center
+                    + &Vec3 {
+                        x: 0.1 * phi.sin(),
+                        y: 0.2 * theta.cos(),
+                        z: 0.05,
+                    };
+                let r = Ray3::new(o, &target - &o);
+                let rec = b.hit(&r, 0.0, Real::MAX).unwrap();
+                assert!((&r.point_at_parameter(rec.t) - &rec.p).length() < 1e-4);
+                let (n, out) = farthest_axis(&b, &rec.p);
+                assert!((out - 1.0).abs() < 1e-4);
+                assert!((&rec.n - &n).length() < 1e-4);
+                assert!(rec.n.dot(&r.d) < 0.0);
+                // From inside the ray leaves through a face facing along it.
+                let inside = Ray3::new(target, &o - &target);
+                let rec = b.hit(&inside, 0.0, Real::MAX).unwrap();
+                assert!(rec.n.dot(&inside.d) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn rays_past_oriented_boxes_miss() {
+        let b = oriented_box();
+        let side = b.rotation.rotate(&Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
+        });
+        let along = b.rotation.rotate(&Vec3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        });
+        let past = &b.center + &(&side * 0.26);
+        let r = Ray3::new(&past - &(&along * 3.0), along);
+        assert!(b.hit(&r, 0.0, Real::MAX).is_none());
+        let away = Ray3::new(&b.center + &(&along * 3.0), along);
+        assert!(b.hit(&away, 0.0, Real::MAX).is_none());
+    }
+
+    #[test]
+    fn flat_boxes_get_finite_uvs() {
+        let flat = AxisAlignedBox {
+            bbox: AABBox3 {
+                blf: Vec3::new(),
+                trr: Vec3 {
+                    x: 1.0,
+                    y: 0.0,
+                    z: 1.0,
+                },
+            },
+            material: Box::new(Lambertian::new(Vec3::new())),
+        };
+        let v = |x, y, z| Vec3 { x, y, z };
+        let rays = [
+            Ray3::new(v(0.3, 1.0, 0.6), v(0.0, -1.0, 0.0)),
+            Ray3::new(v(-1.0, -0.5, 0.5), v(1.0, 0.5, 0.0)),
+            Ray3::new(v(0.5, -0.5, -1.0), v(0.0, 0.5, 1.0)),
+        ];
+        for r in rays.iter() {
+            let rec = flat.hit(r, 0.0, Real::MAX).unwrap();
+            assert!(rec.uv.x.is_finite() && rec.uv.y.is_finite());
+        }
+    }
+}
@@ -0,0 +1,432 @@
+use std::sync::Arc;
+
+use super::super::render::hit::{Hitable, Info as HitInfo};
+use super::super::render::light::Light;
+use super::aabbox::{AABBox3, ExpandableToOther, ExpandableToPoint3};
+use super::ray::Ray3;
+use super::vector::{Axis, Vec3};
+use super::Real;
+
+/// Number of bins the centroids are sorted into when looking for a split.
+const BUCKETS_COUNT: usize = 12;
+/// Cost of visiting a node relative to the cost of intersecting an object.
+pub const TRAVERSAL_COST: Real = 0.125;
+/// Nodes waiting to be visited by a ray, a tree can be this deep at most.
+pub const STACK_SIZE: usize = 64;
+/// Nodes this deep are split at the median, which keeps trees over any
+/// object count that fits in memory within `STACK_SIZE`.
+pub const MEDIAN_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Node {
+    pub bbox: AABBox3,
+    /// For a leaf the index of its first object, otherwise the index of its
+    /// second child. The first child always directly follows its parent.
+    pub offset: usize,
+    /// Number of objects in a leaf, zero for interior nodes.
+    pub count: usize,
+    /// The axis children are split along, used to visit the near one first.
+    pub axis: Axis,
+}
+
+impl Node {
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+struct Primitive {
+    index: usize,
+    bbox: AABBox3,
+    center: Vec3,
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    count: usize,
+    bbox: AABBox3,
+}
+
+/// Bounding volume hierarchy built with the surface area heuristic.
+pub struct Bvh<T: Hitable> {
+    pub nodes: Vec<Node>,
+    /// Bounded objects, reordered so every leaf owns a contiguous range.
+    pub objects: Vec<T>,
+    /// Objects without a bounding box, these are tested for every ray.
+    pub unbounded: Vec<T>,
+}
+
+impl<T: Hitable> Bvh<T> {
+    /// `leaf_size` is the most objects a leaf holds, the heuristic may still
+    /// split smaller sets when that is cheaper.
+    pub fn new(objects: Vec<T>, leaf_size: usize) -> Self {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        let mut primitives = Vec::new();
+        for o in objects {
+            match o.bounding_box() {
+                Some(bbox) => {
+                    primitives.push(Primitive {
+                        index: bounded.len(),
+                        bbox,
+                        center: bbox.get_center(),
+                    });
+                    bounded.push(Some(o));
+                }
+                None => unbounded.push(o),
+            }
+        }
+        let mut nodes = Vec::new();
+        if !primitives.is_empty() {
+            Self::build(&mut primitives, 0, leaf_size.max(1), 0, &mut nodes);
+        }
+        let objects = primitives
+            .iter()
+            .map(|p| bounded[p.index].take().unwrap())
+            .collect();
+        Bvh {
+            nodes,
+            objects,
+            unbounded,
+        }
+    }
+
+    /// Puts a tree together from its nodes and the objects it was built
+    /// over, `order` holds the index in `objects` of every object the leaves
+    /// refer to. Objects missing from it are tested for every ray.
+    pub fn from_order(nodes: Vec<Node>, objects: Vec<T>, order: &[usize]) -> Self {
+        if get_depth(&nodes) > STACK_SIZE {
+            panic!("Tree is too deep to traverse.");
+        }
+        let mut slots: Vec<Option<T>> = objects.into_iter().map(Some).collect();
+        let objects = order
+            .iter()
+            .map(|i| slots[*i].take().expect("Object is ordered twice."))
+            .collect();
+        Bvh {
+            nodes,
+            objects,
+            unbounded: slots.into_iter().flatten().collect(),
+        }
+    }
+
+    /// Recomputes every box from the current bounds of the objects while
+    /// keeping the topology, in one pass over the nodes. Objects that moved
+    /// far from their neighbours leave loose boxes behind, `get_cost` tells
+    /// how much that hurts.
+    pub fn refit(&mut self) {
+        // Children always come after their parent, so walking backwards
+        // visits them first.
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i];
+            let mut bbox = AABBox3::new();
+            if node.is_leaf() {
+                for o in &self.objects[node.offset..node.offset + node.count] {
+                    if let Some(b) = o.bounding_box() {
+                        ExpandableToOther::expand(&mut bbox, &b);
+                    }
+                }
+            } else {
+                ExpandableToOther::expand(&mut bbox, &self.nodes[i + 1].bbox);
+                ExpandableToOther::expand(&mut bbox, &self.nodes[node.offset].bbox);
+            }
+            self.nodes[i].bbox = bbox;
+        }
+    }
+
+    /// Expected cost of tracing a ray that hits the root box under the
+    /// surface area heuristic, in object intersections. Lower is better,
+    /// comparing it with the cost right after a build tells when a refitted
+    /// tree has degraded enough to be rebuilt.
+    pub fn get_cost(&self) -> Real {
+        let root_area = match self.nodes.first() {
+            Some(root) => root.bbox.get_surface_area(),
+            None => return 0.0,
+        };
+        if root_area <= 0.0 {
+            return self.objects.len() as Real;
+        }
+        let mut cost = 0.0;
+        for node in &self.nodes {
+            let weight = if node.is_leaf() {
+                node.count as Real
+            } else {
+                TRAVERSAL_COST
+            };
+            cost += weight * node.bbox.get_surface_area();
+        }
+        cost / root_area
+    }
+
+    fn build(
+        primitives: &mut [Primitive],
+        first: usize,
+        leaf_size: usize,
+        depth: usize,
+        nodes: &mut Vec<Node>,
+    ) {
+        let node_index = nodes.len();
+        let mut bbox = AABBox3::new();
+        let mut centers = AABBox3::new();
+        for p in primitives.iter() {
+            ExpandableToOther::expand(&mut bbox, &p.bbox);
+            ExpandableToPoint3::expand(&mut centers, &p.center);
+        }
+        nodes.push(Node {
+            bbox,
+            offset: first,
+            count: primitives.len(),
+            axis: Axis::X,
+        });
+        if primitives.len() == 1 {
+            return;
+        }
+        let split = if depth < MEDIAN_DEPTH {
+            Self::find_split(primitives, &bbox, &centers)
+        } else {
+            None
+        };
+        let mid = match split {
+            Some((axis, bucket, cost)) => {
+                if cost >= primitives.len() as Real && primitives.len() <= leaf_size {
+                    return;
+                }
+                nodes[node_index].axis = axis;
+                let low = centers.blf[axis];
+                let extent = centers.trr[axis] - low;
+                partition(primitives, |p| {
+                    bucket_index(p.center[axis], low, extent) < bucket
+                })
+            }
+            None => {
+                // Too deep, or all centroids coincide and no split
+                // separates them.
+                if primitives.len() <= leaf_size {
+                    return;
+                }
+                let axis = centers.get_longest_axis();
+                nodes[node_index].axis = axis;
+                let mid = primitives.len() / 2;
+                primitives
+                    .select_nth_unstable_by(mid, |a, b| a.center[axis].total_cmp(&b.center[axis]));
+                mid
+            }
+        };
+        nodes[node_index].count = 0;
+        let (left, right) = primitives.split_at_mut(mid);
+        Self::build(left, first, leaf_size, depth + 1, nodes);
+        nodes[node_index].offset = nodes.len();
+        Self::build(right, first + mid, leaf_size, depth + 1, nodes);
+    }
+
+    /// Returns the axis, the first bucket of the right side and the estimated
+    /// cost of the cheapest split.
+    fn find_split(
+        primitives: &[Primitive],
+        bbox: &AABBox3,
+        centers: &AABBox3,
+    ) -> Option<(Axis, usize, Real)> {
+        let area = bbox.get_surface_area();
+        let mut best: Option<(Axis, usize, Real)> = None;
+        for axis in Axis::ALL.iter() {
+            let axis = *axis;
+            let low = centers.blf[axis];
+            let extent = centers.trr[axis] - low;
+            if extent <= 0.0 {
+                continue;
+            }
+            let mut buckets = [Bucket {
+                count: 0,
+                bbox: AABBox3::new(),
+            }; BUCKETS_COUNT];
+            for p in primitives {
+                let b = &mut buckets[bucket_index(p.center[axis], low, extent)];
+                b.count += 1;
+                ExpandableToOther::expand(&mut b.bbox, &p.bbox);
+            }
+            // Sweep from the right first so every split's right side is ready.
+            let mut right_areas = [0.0; BUCKETS_COUNT];
+            let mut right_counts = [0usize; BUCKETS_COUNT];
+            let mut right_bbox = AABBox3::new();
+            let mut right_count = 0;
+            for i in (1..BUCKETS_COUNT).rev() {
+                ExpandableToOther::expand(&mut right_bbox, &buckets[i].bbox);
+                right_count += buckets[i].count;
+                right_areas[i] = right_bbox.get_surface_area();
+                right_counts[i] = right_count;
+            }
+            let mut left_bbox = AABBox3::new();
+            let mut left_count = 0;
+            for i in 1..BUCKETS_COUNT {
+                ExpandableToOther::expand(&mut left_bbox, &buckets[i - 1].bbox);
+                left_count += buckets[i - 1].count;
+                if left_count == 0 || right_counts[i] == 0 {
+                    continue;
+                }
+                let cost = TRAVERSAL_COST
+                    + (left_count as Real * left_bbox.get_surface_area()
+                        + right_counts[i] as Real * right_areas[i])
+                        / area;
+                if best.is_none_or(|b| cost < b.2) {
+                    best = Some((axis, i, cost));
+                }
+            }
+        }
+        best
+    }
+}
+
+fn bucket_index(c: Real, low: Real, extent: Real) -> usize {
+    let b = ((c - low) / extent * BUCKETS_COUNT as Real) as usize;
+    b.min(BUCKETS_COUNT - 1)
+}
+
+/// Nodes on the longest path from the root to a leaf of a tree laid out
+/// the way `Bvh` keeps it.
+pub fn get_depth(nodes: &[Node]) -> usize {
+    let mut depths = vec![1; nodes.len()];
+    let mut deepest = 0;
+    for (i, node) in nodes.iter().enumerate() {
+        let depth = depths[i];
+        deepest = deepest.max(depth);
+        if !node.is_leaf() {
+            depths[i + 1] = depth + 1;
+            depths[node.offset] = depth + 1;
+        }
+    }
+    deepest
+}
+
+/// Moves the elements matching `pred` to the front and returns their count.
+fn partition<P, F>(items: &mut [P], pred: F) -> usize
+where
+    F: Fn(&P) -> bool,
+{
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(mid, i);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+impl<T: Hitable> Hitable for Bvh<T> {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        let mut closest = t_max;
+        let mut result = None;
+        for o in &self.unbounded {
+            if let Some(rec) = o.hit(r, t_min, closest) {
+                closest = rec.t;
+                result = Some(rec);
+            }
+        }
+        if self.nodes.is_empty() {
+            return result;
+        }
+        let mut stack = [0usize; STACK_SIZE];
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let i = stack[len];
+            let node = &self.nodes[i];
+            if node.bbox.intersection(r, t_min, closest).is_none() {
+                continue;
+            }
+            if node.is_leaf() {
+                for o in &self.objects[node.offset..node.offset + node.count] {
+                    if let Some(rec) = o.hit(r, t_min, closest) {
+                        closest = rec.t;
+                        result = Some(rec);
+                    }
+                }
+            } else {
+                // The near child goes on top.
+                let (near, far) = if r.d[node.axis] < 0.0 {
+                    (node.offset, i + 1)
+                } else {
+                    (i + 1, node.offset)
+                };
+                stack[len] = far;
+                stack[len + 1] = near;
+                len += 2;
+            }
+        }
+        result
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        match self.nodes.first() {
+            Some(root) => Some(root.bbox),
+            None => Some(AABBox3::new()),
+        }
+    }
+
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Light>>) {
+        for o in self.objects.iter().chain(&self.unbounded) {
+            o.collect_emitters(lights);
+        }
+    }
+}
+
+// Single precision can not hold boxes as far apart as these tests need.
+#[cfg(all(test, not(feature = "single-precision")))]
+mod tests {
+    use super::super::super::render::material::Lambertian;
+    use super::super::boxes::AxisAlignedBox;
+    use super::*;
+
+    /// Boxes that grow along +x, each one four times as far as the last,
+    /// so most splits the heuristic likes only peel off the farthest ones.
+    fn spreading_boxes(count: usize) -> Vec<AxisAlignedBox> {
+        (0..count)
+            .map(|i| {
+                let x = (4.0 as Real).powi(i as i32);
+                let w = 0.25 * x;
+                AxisAlignedBox {
+                    bbox: AABBox3 {
+                        blf: Vec3 { x, y: 0.0, z: 0.0 },
+                        trr: Vec3 {
+                            x: x + w,
+                            y: w,
+                            z: w,
+                        },
+                    },
+                    material: Box::new(Lambertian::new(Vec3::new())),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lopsided_trees_fit_the_traversal_stack() {
+        let count = 120;
+        for linear in [false, true].iter() {
+            let bvh = if *linear {
+                Bvh::new_linear(spreading_boxes(count), 1, true)
+            } else {
+                Bvh::new(spreading_boxes(count), 1)
+            };
+            assert!(get_depth(&bvh.nodes) <= STACK_SIZE);
+            for b in bvh.objects.iter() {
+                let top = b.bbox.trr.y;
+                let o = Vec3 {
+                    x: b.bbox.get_center().x,
+                    y: 2.0 * top,
+                    z: 0.5 * top,
+                };
+                let down = Vec3 {
+                    x: 0.0,
+                    y: -1.0,
+                    z: 0.0,
+                };
+                let rec = bvh.hit(&Ray3::new(o, down), 0.0, Real::MAX).unwrap();
+                assert!((rec.t - top).abs() <= top * 1e-4);
+            }
+        }
+    }
+}
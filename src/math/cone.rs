@@ -0,0 +1,188 @@
+use super::super::render::hit::{Hitable, Info as HitInfo};
+use super::super::render::material::Material;
+use super::aabbox::{AABBox3, ExpandableToPoint3};
+use super::consts::PI;
+use super::cylinder::point_around_axis;
+use super::ray::Ray3;
+use super::vector::{Vec2, Vec3};
+use super::Real;
+
+/// Side surface of a cone with a `radius` wide base, close it with a `Disk`
+/// when a cap is needed. u is the angle around the axis over 2 pi and v the
+/// height over the distance from the base to the apex.
+pub struct Cone {
+    base: Vec3,
+    axis: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    height: Real,
+    radius: Real,
+    pub material: Box<dyn Material>,
+}
+
+impl Cone {
+    pub fn new(base: Vec3, apex: Vec3, radius: Real, material: Box<dyn Material>) -> Self {
+        let axis = &apex - &base;
+        let height = axis.length();
+        let axis = &axis / height;
+        let (tangent, bitangent) = axis.get_perpendiculars();
+        Cone {
+            base,
+            axis,
+            tangent,
+            bitangent,
+            height,
+            radius,
+            material,
+        }
+    }
+
+    pub fn get_base(&self) -> &Vec3 {
+        &self.base
+    }
+
+    pub fn get_apex(&self) -> Vec3 {
+        &self.base + &(&self.axis * self.height)
+    }
+
+    pub fn get_radius(&self) -> Real {
+        self.radius
+    }
+}
+
+impl Hitable for Cone {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        // At the height h the distance from the axis is k (height - h).
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let oc = &r.o - &self.base;
+        let dh = r.d.dot(&self.axis);
+        let s = self.height - oc.dot(&self.axis);
+        let d_perp = &r.d - &(&self.axis * dh);
+        let oc_perp = &oc - &(&self.axis * oc.dot(&self.axis));
+        let a = d_perp.dot(&d_perp) - k2 * dh * dh;
+        let b = oc_perp.dot(&d_perp) + k2 * s * dh;
+        let c = oc_perp.dot(&oc_perp) - k2 * s * s;
+        let candidates = if a == 0.0 {
+            // The ray is parallel to a line of the surface, it crosses it once.
+            if b == 0.0 {
+                return None;
+            }
+            let t = -c / (2.0 * b);
+            [t, t]
+        } else {
+            let discriminant = b * b - a * c;
+            if discriminant < 0.0 {
+                return None;
+            }
+            let sqrt_d = discriminant.sqrt();
+            let t0 = (-b - sqrt_d) / a;
+            let t1 = (-b + sqrt_d) / a;
+            [t0.min(t1), t0.max(t1)]
+        };
+        for t in candidates.iter() {
+            let t = *t;
+            if t <= t_min || t >= t_max {
+                continue;
+            }
+            let local = &r.point_at_parameter(t) - &self.base;
+            let h = local.dot(&self.axis);
+            // Also rejects the mirrored cone above the apex.
+            if h < 0.0 || h > self.height {
+                continue;
+            }
+            let radial = &local - &(&self.axis * h);
+            let phi = radial.dot(&self.bitangent).atan2(radial.dot(&self.tangent));
+            let n = &(&radial.normalized() * self.height) + &(&self.axis * self.radius);
+            let n = n.normalized();
+            let radius = self.radius * (self.height - h) / self.height;
+            let (p, p_error) = point_around_axis(&self.base, &self.axis, h, &radial, radius);
+            return Some(HitInfo {
+                t,
+                p,
+                p_error,
+                n,
+                ng: n,
+                uv: Vec2 {
+                    x: (phi + PI) / (2.0 * PI),
+                    y: h / self.height,
+                },
+                m: self.material.as_ref(),
+            });
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        let mut b = AABBox3::around_circle(&self.base, &self.axis, self.radius);
+        b.expand(&self.get_apex());
+        Some(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::render::material::Lambertian;
+    use super::*;
+
+    fn v(x: Real, y: Real, z: Real) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    fn cone() -> Cone {
+        Cone::new(
+            v(1.0, -0.5, 0.5),
+            v(1.5, 1.5, 0.0),
+            0.75,
+            Box::new(Lambertian::new(Vec3::new())),
+        )
+    }
+
+    #[test]
+    fn rays_toward_the_side_hit_it_along_its_normal() {
+        let cone = cone();
+        for i in 0..6 {
+            for j in 1..6 {
+                let phi = i as Real * 1.1;
+                let h = j as Real / 6.0 * cone.height;
+                let radial = &(&cone.tangent * phi.cos()) + &(&cone.bitangent * phi.sin());
+                let target = &(&cone.base + &(&cone.axis * h))
+                    + &(&radial * (cone.radius * (1.0 - h / cone.height)));
+                let normal = (&(&radial * cone.height) + &(&cone.axis * cone.radius)).normalized();
+                let o = &(&target + &(&normal * 2.0)) + &(&cone.axis * 0.3);
+                let r = Ray3::new(o, &target - &o);
+                let rec = cone.hit(&r, 0.0, Real::MAX).unwrap();
+                assert!((&rec.p - &target).length() < 1e-4);
+                assert!((&r.point_at_parameter(rec.t) - &rec.p).length() < 1e-4);
+                assert!((&rec.n - &normal).length() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn rays_past_the_apex_or_below_the_base_miss() {
+        let cone = cone();
+        let across = cone.tangent;
+        let over = &cone.get_apex() + &(&cone.axis * 0.5);
+        assert!(cone
+            .hit(&Ray3::new(&over - &(&across * 3.0), across), 0.0, Real::MAX)
+            .is_none());
+        let under = &cone.base - &(&cone.axis * 0.1);
+        assert!(cone
+            .hit(
+                &Ray3::new(&under - &(&across * 3.0), across),
+                0.0,
+                Real::MAX
+            )
+            .is_none());
+        // Would only cross the mirrored cone above the apex.
+        let mirrored = &cone.get_apex() + &(&cone.axis * cone.height);
+        assert!(cone
+            .hit(
+                &Ray3::new(&mirrored - &(&across * 3.0), across),
+                0.0,
+                Real::MAX
+            )
+            .is_none());
+    }
+}
@@ -0,0 +1,193 @@
+use super::super::render::hit::{Hitable, Info as HitInfo};
+use super::super::render::material::Material;
+use super::aabbox::{AABBox3, ExpandableToOther};
+use super::consts::PI;
+use super::float::gamma;
+use super::ray::Ray3;
+use super::vector::{Vec2, Vec3};
+use super::Real;
+
+/// Side surface of a cylinder from `base` to `top`, close it with `Disk`s
+/// when caps are needed. u is the angle around the axis over 2 pi and v the
+/// height over the length.
+pub struct Cylinder {
+    base: Vec3,
+    axis: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    height: Real,
+    radius: Real,
+    pub material: Box<dyn Material>,
+}
+
+impl Cylinder {
+    pub fn new(base: Vec3, top: Vec3, radius: Real, material: Box<dyn Material>) -> Self {
+        let axis = &top - &base;
+        let height = axis.length();
+        let axis = &axis / height;
+        let (tangent, bitangent) = axis.get_perpendiculars();
+        Cylinder {
+            base,
+            axis,
+            tangent,
+            bitangent,
+            height,
+            radius,
+            material,
+        }
+    }
+
+    pub fn get_base(&self) -> &Vec3 {
+        &self.base
+    }
+
+    pub fn get_top(&self) -> Vec3 {
+        &self.base + &(&self.axis * self.height)
+    }
+
+    pub fn get_radius(&self) -> Real {
+        self.radius
+    }
+}
+
+/// The point `h` along the unit `axis` from `base` and `radius` away from
+/// the axis toward `radial`, with the bound of its rounding error. Rebuilt
+/// this way hit points lie on the surface whatever error the ray distance
+/// has.
+pub fn point_around_axis(
+    base: &Vec3,
+    axis: &Vec3,
+    h: Real,
+    radial: &Vec3,
+    radius: Real,
+) -> (Vec3, Vec3) {
+    let length = radial.length();
+    let radial = if length > 0.0 {
+        radial * (radius / length)
+    } else {
+        *radial
+    };
+    let along = axis * h;
+    let p = &(base + &along) + &radial;
+    let p_error = &(&(&base.abs() + &along.abs()) + &radial.abs()) * gamma(6);
+    (p, p_error)
+}
+
+impl Hitable for Cylinder {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        // Distance from the axis only depends on the parts perpendicular to it.
+        let oc = &r.o - &self.base;
+        let d_perp = &r.d - &(&self.axis * r.d.dot(&self.axis));
+        let oc_perp = &oc - &(&self.axis * oc.dot(&self.axis));
+        let a = d_perp.dot(&d_perp);
+        if a == 0.0 {
+            return None;
+        }
+        let b = oc_perp.dot(&d_perp);
+        let c = oc_perp.dot(&oc_perp) - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let sqrt_d = discriminant.sqrt();
+        for t in [(-b - sqrt_d) / a, (-b + sqrt_d) / a].iter() {
+            let t = *t;
+            if t <= t_min || t >= t_max {
+                continue;
+            }
+            let local = &r.point_at_parameter(t) - &self.base;
+            let h = local.dot(&self.axis);
+            if h < 0.0 || h > self.height {
+                continue;
+            }
+            let radial = &local - &(&self.axis * h);
+            let phi = radial.dot(&self.bitangent).atan2(radial.dot(&self.tangent));
+            let (p, p_error) = point_around_axis(&self.base, &self.axis, h, &radial, self.radius);
+            let n = radial.normalized();
+            return Some(HitInfo {
+                t,
+                p,
+                p_error,
+                n,
+                ng: n,
+                uv: Vec2 {
+                    x: (phi + PI) / (2.0 * PI),
+                    y: h / self.height,
+                },
+                m: self.material.as_ref(),
+            });
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        let mut b = AABBox3::around_circle(&self.base, &self.axis, self.radius);
+        b.expand(&AABBox3::around_circle(
+            &self.get_top(),
+            &self.axis,
+            self.radius,
+        ));
+        Some(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::render::material::Lambertian;
+    use super::*;
+
+    fn v(x: Real, y: Real, z: Real) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    fn cylinder() -> Cylinder {
+        Cylinder::new(
+            v(-0.5, 0.0, 1.0),
+            v(0.5, 2.0, 1.5),
+            0.5,
+            Box::new(Lambertian::new(Vec3::new())),
+        )
+    }
+
+    #[test]
+    fn rays_toward_the_side_hit_it_along_its_normal() {
+        let cylinder = cylinder();
+        for i in 0..6 {
+            for j in 1..6 {
+                let phi = i as Real * 1.1;
+                let h = j as Real / 6.0 * cylinder.height;
+                let normal = &(&cylinder.tangent * phi.cos()) + &(&cylinder.bitangent * phi.sin());
+                let target =
+                    &(&cylinder.base + &(&cylinder.axis * h)) + &(&normal * cylinder.radius);
+                let o = &(&target + &(&normal * 2.0)) - &(&cylinder.axis * 0.3);
+                let r = Ray3::new(o, &target - &o);
+                let rec = cylinder.hit(&r, 0.0, Real::MAX).unwrap();
+                assert!((&rec.p - &target).length() < 1e-4);
+                assert!((&r.point_at_parameter(rec.t) - &rec.p).length() < 1e-4);
+                assert!((&rec.n - &normal).length() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn rays_beside_or_along_the_axis_miss() {
+        let cylinder = cylinder();
+        let across = cylinder.tangent;
+        let beside = &cylinder.base + &(&cylinder.bitangent * 0.6);
+        assert!(cylinder
+            .hit(
+                &Ray3::new(&beside - &(&across * 3.0), across),
+                0.0,
+                Real::MAX
+            )
+            .is_none());
+        let over = &cylinder.get_top() + &(&cylinder.axis * 0.1);
+        assert!(cylinder
+            .hit(&Ray3::new(&over - &(&across * 3.0), across), 0.0, Real::MAX)
+            .is_none());
+        // Inside the tube along the axis there is no side to hit.
+        assert!(cylinder
+            .hit(&Ray3::new(cylinder.base, cylinder.axis), 0.0, Real::MAX)
+            .is_none());
+    }
+}
@@ -0,0 +1,144 @@
+use super::super::render::hit::{Hitable, Info as HitInfo};
+use super::super::render::material::Material;
+use super::aabbox::AABBox3;
+use super::consts::PI;
+use super::plane::point_on_plane;
+use super::ray::Ray3;
+use super::vector::{Vec2, Vec3};
+use super::Real;
+
+/// Flat disk facing `normal`, u is the angle around the normal over 2 pi
+/// and v the distance from the center over the radius.
+pub struct Disk {
+    center: Vec3,
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    radius: Real,
+    pub material: Box<dyn Material>,
+}
+
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: Real, material: Box<dyn Material>) -> Self {
+        let normal = normal.normalized();
+        let (tangent, bitangent) = normal.get_perpendiculars();
+        Disk {
+            center,
+            normal,
+            tangent,
+            bitangent,
+            radius,
+            material,
+        }
+    }
+
+    pub fn get_center(&self) -> &Vec3 {
+        &self.center
+    }
+
+    pub fn get_normal(&self) -> &Vec3 {
+        &self.normal
+    }
+
+    pub fn get_radius(&self) -> Real {
+        self.radius
+    }
+}
+
+impl Hitable for Disk {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        let denom = self.normal.dot(&r.d);
+        if denom == 0.0 {
+            return None;
+        }
+        let t = self.normal.dot(&(&self.center - &r.o)) / denom;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+        let local = &r.point_at_parameter(t) - &self.center;
+        let planar = Vec2 {
+            x: local.dot(&self.tangent),
+            y: local.dot(&self.bitangent),
+        };
+        let dist2 = planar.x * planar.x + planar.y * planar.y;
+        if dist2 > self.radius * self.radius {
+            return None;
+        }
+        let phi = planar.y.atan2(planar.x);
+        let (p, p_error) = point_on_plane(&self.center, &self.tangent, &self.bitangent, &planar);
+        Some(HitInfo {
+            t,
+            p,
+            p_error,
+            n: self.normal,
+            ng: self.normal,
+            uv: Vec2 {
+                x: (phi + PI) / (2.0 * PI),
+                y: dist2.sqrt() / self.radius,
+            },
+            m: self.material.as_ref(),
+        })
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        Some(AABBox3::around_circle(
+            &self.center,
+            &self.normal,
+            self.radius,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::render::material::Lambertian;
+    use super::*;
+
+    fn v(x: Real, y: Real, z: Real) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    fn disk() -> Disk {
+        Disk::new(
+            v(0.5, 1.0, -1.0),
+            v(0.2, 1.0, 0.4),
+            0.8,
+            Box::new(Lambertian::new(Vec3::new())),
+        )
+    }
+
+    #[test]
+    fn rays_toward_the_disk_hit_it_on_its_plane() {
+        let disk = disk();
+        for i in 0..6 {
+            for j in 0..4 {
+                let phi = i as Real * 1.1;
+                let target = &disk.center
+                    + &(&(&(&disk.tangent * phi.cos()) + &(&disk.bitangent * phi.sin()))
+                        * (j as Real * 0.25 * disk.radius));
+                for side in [1.0, -1.0].iter() {
+                    let o = &(&target + &(&disk.normal * (2.0 * side))) + &(&disk.tangent * 0.5);
+                    let r = Ray3::new(o, &target - &o);
+                    let rec = disk.hit(&r, 0.0, Real::MAX).unwrap();
+                    assert!((&rec.p - &target).length() < 1e-4);
+                    assert!((&r.point_at_parameter(rec.t) - &rec.p).length() < 1e-4);
+                    assert!((&rec.n - &disk.normal).length() < 1e-4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rays_beside_or_along_the_disk_miss() {
+        let disk = disk();
+        let beside = &disk.center + &(&disk.tangent * (disk.radius * 1.01));
+        let down = -&disk.normal;
+        assert!(disk
+            .hit(&Ray3::new(&beside + &disk.normal, down), 0.0, Real::MAX)
+            .is_none());
+        let along = Ray3::new(&disk.center - &(&disk.tangent * 2.0), disk.tangent);
+        assert!(disk.hit(&along, 0.0, Real::MAX).is_none());
+        let away = Ray3::new(&disk.center + &disk.normal, disk.normal);
+        assert!(disk.hit(&away, 0.0, Real::MAX).is_none());
+    }
+}
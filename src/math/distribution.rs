@@ -0,0 +1,122 @@
+use super::vector::Vec2;
+use super::Real;
+
+/// Piecewise constant density over [0, 1) with a step for each value of a
+/// non negative function, sampled by inverting its running sum.
+pub struct Distribution1D {
+    func: Vec<Real>,
+    /// Running integral, `cdf[i]` is the probability of landing below step
+    /// `i` and the last one is 1.
+    cdf: Vec<Real>,
+    integral: Real,
+}
+
+impl Distribution1D {
+    /// A function that is zero everywhere is sampled uniformly.
+    pub fn new(func: Vec<Real>) -> Self {
+        let n = func.len();
+        if n == 0 {
+            panic!("Distribution needs at least one value.");
+        }
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as Real;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as Real / n as Real
+            };
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn get_count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Integral of the function over [0, 1).
+    pub fn get_integral(&self) -> Real {
+        self.integral
+    }
+
+    /// returns: (the point in [0, 1) for `u` uniform in [0, 1), its density,
+    /// the index of its step)
+    pub fn sample(&self, u: Real) -> (Real, Real, usize) {
+        let n = self.get_count();
+        let i = (self.cdf.partition_point(|c| *c <= u) - 1).min(n - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        let x = ((i as Real + du) / n as Real).min(1.0 - Real::EPSILON);
+        (x, self.pdf_of_step(i), i)
+    }
+
+    /// Density of the point `x` in [0, 1).
+    pub fn pdf(&self, x: Real) -> Real {
+        self.pdf_of_step(self.step_of(x))
+    }
+
+    fn step_of(&self, x: Real) -> usize {
+        let n = self.get_count();
+        ((x * n as Real).max(0.0) as usize).min(n - 1)
+    }
+
+    fn pdf_of_step(&self, i: usize) -> Real {
+        if self.integral > 0.0 {
+            self.func[i] / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Piecewise constant density over the unit square from a grid of non
+/// negative values, rows along y and columns along x. The row is picked by
+/// its integral first, then the column inside it.
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `func` holds `width` values for each row, from y = 0 up.
+    pub fn new(func: &[Real], width: usize) -> Self {
+        if width == 0 || func.is_empty() || !func.len().is_multiple_of(width) {
+            panic!("Distribution grid does not match its width.");
+        }
+        let rows: Vec<Distribution1D> = func
+            .chunks(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|r| r.get_integral()).collect());
+        Self { rows, marginal }
+    }
+
+    /// Integral of the function over the unit square.
+    pub fn get_integral(&self) -> Real {
+        self.marginal.get_integral()
+    }
+
+    /// returns: (the point for `u` uniform in the unit square, its density)
+    pub fn sample(&self, u: &Vec2) -> (Vec2, Real) {
+        let (y, pdf_y, row) = self.marginal.sample(u.y);
+        let (x, pdf_x, _) = self.rows[row].sample(u.x);
+        (Vec2 { x, y }, pdf_x * pdf_y)
+    }
+
+    /// Density of the point `p` of the unit square.
+    pub fn pdf(&self, p: &Vec2) -> Real {
+        let row = self.marginal.step_of(p.y);
+        self.rows[row].pdf(p.x) * self.marginal.pdf_of_step(row)
+    }
+}
@@ -0,0 +1,53 @@
+use super::vector::{Axis, Vec3};
+use super::Real;
+
+/// Bound of the relative error of `n` chained rounded operations,
+/// (1 + e)^n - 1 <= gamma(n) with e the unit roundoff (Higham 2002).
+pub const fn gamma(n: u32) -> Real {
+    let e = Real::EPSILON * 0.5;
+    n as Real * e / (1.0 - n as Real * e)
+}
+
+/// The smallest value greater than `v`.
+pub fn next_up(v: Real) -> Real {
+    if v.is_infinite() && v > 0.0 {
+        return v;
+    }
+    // -0.0 and 0.0 have the same successor.
+    let v = if v == 0.0 { 0.0 } else { v };
+    let bits = v.to_bits();
+    Real::from_bits(if v >= 0.0 { bits + 1 } else { bits - 1 })
+}
+
+/// The largest value less than `v`.
+pub fn next_down(v: Real) -> Real {
+    if v.is_infinite() && v < 0.0 {
+        return v;
+    }
+    let v = if v == 0.0 { -0.0 } else { v };
+    let bits = v.to_bits();
+    Real::from_bits(if v > 0.0 { bits - 1 } else { bits + 1 })
+}
+
+/// Moves the point `p`, known up to `p_error` per axis, along the normal `n`
+/// to the side `w` points to, just past the box the true point lies in. A
+/// ray leaving the result toward `w` can not hit the surface `p` is on
+/// again at its start, whatever the scale of the scene (Pharr et al.,
+/// Physically Based Rendering 3rd edition, 3.9.5).
+pub fn offset_ray_origin(p: &Vec3, p_error: &Vec3, n: &Vec3, w: &Vec3) -> Vec3 {
+    let d = n.abs().dot(p_error);
+    let mut offset = n * d;
+    if w.dot(n) < 0.0 {
+        offset = -&offset;
+    }
+    let mut po = p + &offset;
+    // Rounding the sum may have moved it back toward the surface.
+    for a in Axis::ALL.iter() {
+        if offset[*a] > 0.0 {
+            po[*a] = next_up(po[*a]);
+        } else if offset[*a] < 0.0 {
+            po[*a] = next_down(po[*a]);
+        }
+    }
+    po
+}
@@ -1,126 +1,117 @@
-use std;
-
-use ::math::aabbox::{
-    AABBox3,
-    ExpandableToOther,
-};
-use ::math::triangle::{
-    Triangle,
-};
-use ::math::ray::Ray3;
-use ::math::vector::{
-    Vec3,
-    Axis,
-};
-use ::render::vertex::Vertex;
+use super::aabbox::{AABBox3, ExpandableToOther};
+use super::ray::Ray3;
+use super::triangle::Triangle;
+use super::vector::{Axis, Vec3};
+use super::Real;
 
 pub struct KDNode {
-    pub area:    AABBox3,
-    pub left:    Option<Box<KDNode>>,
-    pub right:   Option<Box<KDNode>>,
+    pub area: AABBox3,
+    /// Axis the children are split along, the left one holds the lower side.
+    pub axis: Axis,
+    pub left: Option<Box<KDNode>>,
+    pub right: Option<Box<KDNode>>,
     pub indices: Vec<usize>,
 }
 
 impl KDNode {
     pub fn new() -> KDNode {
         KDNode {
-            area:    AABBox3::new(),
-            left:    None,
-            right:   None,
+            area: AABBox3::new(),
+            axis: Axis::X,
+            left: None,
+            right: None,
             indices: Vec::new(),
         }
     }
 
-    pub fn build(indices: &Vec<usize>, vertices: &Vec<Vertex>, triangles: &Vec<Triangle>) -> Option<Box<KDNode>> {
+    pub fn build(indices: &[usize], triangles: &[Triangle]) -> Option<Box<KDNode>> {
         let mut node = KDNode::new();
-        if indices.len() < 1 {
+        if indices.is_empty() {
             return None;
         }
         if indices.len() < 2 {
             node.indices.push(indices[0]);
-            node.area = triangles[indices[0]].get_aabb(vertices);
+            node.area = triangles[indices[0]].get_aabb();
             return Some(Box::new(node));
         }
 
-        node.area = triangles[indices[0]].get_aabb(vertices);
+        node.area = triangles[indices[0]].get_aabb();
         let mut midpt = Vec3::new();
-        let tris_recp = 1f64 / (indices.len() as f64);
+        let tris_recp = 1.0 / (indices.len() as Real);
 
         for index in indices {
-            node.area.expand(&triangles[*index].get_aabb(vertices));
-            midpt += triangles[*index].get_midpoint(vertices) * tris_recp;
+            node.area.expand(&triangles[*index].get_aabb());
+            midpt += &(&triangles[*index].get_midpoint() * tris_recp);
         }
 
         let mut left_indices = Vec::new();
         let mut right_indices = Vec::new();
-        let axis = node.area.get_longest_axis();
+        node.axis = node.area.get_longest_axis();
+        let split = midpt[node.axis];
 
-        // TODO for performance imporvement: put match block out of the for loop.
         for index in indices {
-            match axis {
-                Axis::X => if midpt.x >= triangles[*index].get_midpoint(vertices).x { right_indices.push(*index); } else { left_indices.push(*index); },
-                Axis::Y => if midpt.y >= triangles[*index].get_midpoint(vertices).y { right_indices.push(*index); } else { left_indices.push(*index); },
-                Axis::Z => if midpt.z >= triangles[*index].get_midpoint(vertices).z { right_indices.push(*index); } else { left_indices.push(*index); },
-                _ => panic!("Unexpected Axis value.")
+            if triangles[*index].get_midpoint()[node.axis] < split {
+                left_indices.push(*index);
+            } else {
+                right_indices.push(*index);
             }
         }
 
-        if left_indices.len() == 0 || right_indices.len() == 0 {
-            node.indices = indices.clone();
-            // TODO for performance imporvement: I thinck these following three lines have redundant calculating,
-            //      I already calulated them, I ave doubt about it..
+        if left_indices.is_empty() || right_indices.is_empty() {
+            node.indices = indices.to_vec();
             return Some(Box::new(node));
         }
-        node.left = KDNode::build(&left_indices, vertices, triangles);
-        node.right = KDNode::build(&right_indices, vertices, triangles);
+        node.left = KDNode::build(&left_indices, triangles);
+        node.right = KDNode::build(&right_indices, triangles);
         Some(Box::new(node))
     }
 
-    pub fn hit(node: &KDNode, ray: &Ray3, tmin: f64, vertices: &Vec<Vertex>, triangles: &Vec<Triangle>) -> Option<(f64, f64, f64, usize)> {
-        let (does_inter, dist) = node.area.intersection(ray);
-        if !does_inter {
-            return None;
-        }
-        if dist.gt(&tmin) {
-            return None;
-        }
-        match node.left {
-            Some(ref left_node_p) => {
-                let l = KDNode::hit(&*left_node_p, ray, tmin, vertices, triangles);
-                if l.is_some() {
-                    return l;
+    /// Returns the closest hit inside (`tmin`, `tmax`) as distance,
+    /// barycentric coordinates and triangle index.
+    pub fn hit(
+        node: &KDNode,
+        ray: &Ray3,
+        tmin: Real,
+        tmax: Real,
+        triangles: &[Triangle],
+    ) -> Option<(Real, Real, Real, usize)> {
+        node.area.intersection(ray, tmin, tmax)?;
+        if node.left.is_none() && node.right.is_none() {
+            let mut result = None;
+            let mut t = tmax;
+            for index in node.indices.iter() {
+                if let Some((tt, u, v)) = triangles[*index].intersect(ray, tmin, t) {
+                    t = tt;
+                    result = Some((tt, u, v, *index));
                 }
             }
-            None => {}
+            return result;
         }
-        match node.right {
-            Some(ref right_node_p) => {
-                return KDNode::hit(&*right_node_p, ray, tmin, vertices, triangles);
+        let (near, far) = if ray.d[node.axis] < 0.0 {
+            (&node.right, &node.left)
+        } else {
+            (&node.left, &node.right)
+        };
+        let mut result = None;
+        let mut t = tmax;
+        if let Some(ref near) = *near {
+            result = KDNode::hit(near, ray, tmin, t, triangles);
+            if let Some(h) = result {
+                t = h.0;
             }
-            None => {}
         }
-        let mut hit_tri = false;
-        let mut tri_ind: usize = 0;
-        let mut t = tmin;
-        let mut u = 0f64;
-        let mut v = 0f64;
-        for index in node.indices.iter() {
-            match triangles[*index].intersect(ray, tmin, vertices) {
-                Some((_t, _u, _v)) => {
-                    if t.lt(&_t) {
-                        hit_tri = true;
-                        tri_ind = *index;
-                        t = _t;
-                        u = _u;
-                        v = _v;
-                    }
-                }
-                None => {}
+        if let Some(ref far) = *far {
+            // The far child's box test rejects it when it lies beyond `t`.
+            if let Some(h) = KDNode::hit(far, ray, tmin, t, triangles) {
+                result = Some(h);
             }
         }
-        if hit_tri {
-            return Some((t, u, v, tri_ind));
-        }
-        return None;
+        result
+    }
+}
+
+impl Default for KDNode {
+    fn default() -> Self {
+        KDNode::new()
     }
 }
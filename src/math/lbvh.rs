@@ -0,0 +1,427 @@
+use std::thread;
+
+use num_cpus;
+
+use super::super::render::hit::Hitable;
+use super::aabbox::{AABBox3, ExpandableToOther, ExpandableToPoint3};
+use super::bvh::{get_depth, Bvh, Node, MEDIAN_DEPTH, STACK_SIZE, TRAVERSAL_COST};
+use super::vector::Axis;
+use super::Real;
+
+/// Bits of every axis in a Morton code, three of them fill 63 bits.
+const MORTON_BITS: u32 = 21;
+/// Leaves a treelet grows to before its topology is optimized, 2^7 subsets
+/// keep the exhaustive search cheap (Karras and Aila 2013).
+const TREELET_SIZE: usize = 7;
+/// Smaller pieces of work are not worth a thread of their own.
+const PARALLEL_GRAIN: usize = 4096;
+
+#[derive(Clone, Copy)]
+struct Primitive {
+    index: usize,
+    bbox: AABBox3,
+    code: u64,
+}
+
+/// Owned tree the hierarchy is built and optimized in before it is
+/// flattened into `Node`s.
+struct BuildNode {
+    bbox: AABBox3,
+    /// Surface area heuristic cost of the subtree, not divided by the
+    /// area of its root.
+    cost: Real,
+    /// Number of objects below the node.
+    size: usize,
+    kind: Kind,
+}
+
+enum Kind {
+    /// Range of the sorted primitives the leaf owns.
+    Leaf(usize),
+    /// The first child lies on the lower side of `Axis`.
+    Interior(Axis, Box<BuildNode>, Box<BuildNode>),
+}
+
+impl BuildNode {
+    fn leaf(primitives: &[Primitive], first: usize) -> Box<BuildNode> {
+        let mut bbox = AABBox3::new();
+        for p in primitives {
+            ExpandableToOther::expand(&mut bbox, &p.bbox);
+        }
+        Box::new(BuildNode {
+            bbox,
+            cost: primitives.len() as Real * bbox.get_surface_area(),
+            size: primitives.len(),
+            kind: Kind::Leaf(first),
+        })
+    }
+
+    fn interior(axis: Axis, first: Box<BuildNode>, second: Box<BuildNode>) -> Box<BuildNode> {
+        let mut bbox = first.bbox;
+        ExpandableToOther::expand(&mut bbox, &second.bbox);
+        Box::new(BuildNode {
+            bbox,
+            cost: TRAVERSAL_COST * bbox.get_surface_area() + first.cost + second.cost,
+            size: first.size + second.size,
+            kind: Kind::Interior(axis, first, second),
+        })
+    }
+
+    /// Picks the axis the two children are farthest apart along and orders
+    /// them on it, so traversal still visits the near one first.
+    fn interior_ordered(a: Box<BuildNode>, b: Box<BuildNode>) -> Box<BuildNode> {
+        let d = &b.bbox.get_center() - &a.bbox.get_center();
+        let mut axis = Axis::X;
+        for c in Axis::ALL.iter() {
+            if d[*c].abs() > d[axis].abs() {
+                axis = *c;
+            }
+        }
+        if d[axis] < 0.0 {
+            Self::interior(axis, b, a)
+        } else {
+            Self::interior(axis, a, b)
+        }
+    }
+}
+
+impl<T: Hitable + Sync> Bvh<T> {
+    /// Linear bounding volume hierarchy (Lauterbach et al. 2009), objects are
+    /// sorted along a Morton curve through their centroids and the tree
+    /// follows the bits of their codes. Much faster to build than `new` on
+    /// large meshes and runs on every CPU, `optimize_treelets` then
+    /// rearranges small groups of nodes for a tree close to the surface
+    /// area heuristic's. The result uses the same nodes as `new`.
+    pub fn new_linear(objects: Vec<T>, leaf_size: usize, optimize_treelets: bool) -> Self {
+        Self::new_linear_with_order(objects, leaf_size, optimize_treelets).0
+    }
+
+    /// Also returns the order `from_order` needs to put the same tree
+    /// together again without building it.
+    pub fn new_linear_with_order(
+        objects: Vec<T>,
+        leaf_size: usize,
+        optimize_treelets: bool,
+    ) -> (Self, Vec<usize>) {
+        let threads = num_cpus::get().max(1);
+        let boxes = parallel_map(&objects, threads, |o| o.bounding_box());
+        let mut primitives: Vec<Primitive> = boxes
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, b)| {
+                b.map(|bbox| Primitive {
+                    index,
+                    bbox,
+                    code: 0,
+                })
+            })
+            .collect();
+        let mut nodes = Vec::new();
+        if !primitives.is_empty() {
+            assign_morton_codes(&mut primitives, threads);
+            parallel_sort(&mut primitives, threads);
+            let mut root = emit(&primitives, 0, leaf_size.max(1), 0, threads);
+            if optimize_treelets {
+                root = optimize(root, threads);
+            }
+            flatten(&root, &mut nodes);
+            if get_depth(&nodes) > STACK_SIZE {
+                // Restructured treelets deepened a lopsided tree too much.
+                nodes.clear();
+                let root = emit(&primitives, 0, leaf_size.max(1), 0, threads);
+                flatten(&root, &mut nodes);
+            }
+        }
+        let order: Vec<usize> = primitives.iter().map(|p| p.index).collect();
+        (Self::from_order(nodes, objects, &order), order)
+    }
+}
+
+/// Applies `f` to every item, splitting the slice over `threads` threads.
+fn parallel_map<I, O, F>(items: &[I], threads: usize, f: F) -> Vec<O>
+where
+    I: Sync,
+    O: Send,
+    F: Fn(&I) -> O + Sync,
+{
+    if threads < 2 || items.len() < PARALLEL_GRAIN {
+        return items.iter().map(f).collect();
+    }
+    let chunk = items.len().div_ceil(threads);
+    let f = &f;
+    thread::scope(|s| {
+        let handles: Vec<_> = items
+            .chunks(chunk)
+            .map(|c| s.spawn(move || c.iter().map(f).collect::<Vec<O>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    })
+}
+
+fn assign_morton_codes(primitives: &mut [Primitive], threads: usize) {
+    let mut centers = AABBox3::new();
+    for p in primitives.iter() {
+        ExpandableToPoint3::expand(&mut centers, &p.bbox.get_center());
+    }
+    let scale = (1u64 << MORTON_BITS) as Real;
+    let extent = centers.get_diagonal();
+    let chunk = primitives.len().div_ceil(threads).max(PARALLEL_GRAIN);
+    thread::scope(|s| {
+        for c in primitives.chunks_mut(chunk) {
+            let low = &centers.blf;
+            let extent = &extent;
+            s.spawn(move || {
+                for p in c {
+                    let center = p.bbox.get_center();
+                    let mut q = [0u64; 3];
+                    for (i, a) in Axis::ALL.iter().enumerate() {
+                        let o = if extent[*a] > 0.0 {
+                            (center[*a] - low[*a]) / extent[*a]
+                        } else {
+                            0.0
+                        };
+                        q[i] = ((o * scale) as u64).min((1 << MORTON_BITS) - 1);
+                    }
+                    p.code =
+                        (spread_bits(q[0]) << 2) | (spread_bits(q[1]) << 1) | spread_bits(q[2]);
+                }
+            });
+        }
+    });
+}
+
+/// Moves the lowest 21 bits of `v` two places apart from each other.
+fn spread_bits(v: u64) -> u64 {
+    let mut v = v & 0x1f_ffff;
+    v = (v | (v << 32)) & 0x001f_0000_0000_ffff;
+    v = (v | (v << 16)) & 0x001f_0000_ff00_00ff;
+    v = (v | (v << 8)) & 0x100f_00f0_0f00_f00f;
+    v = (v | (v << 4)) & 0x10c3_0c30_c30c_30c3;
+    v = (v | (v << 2)) & 0x1249_2492_4924_9249;
+    v
+}
+
+/// The axis a bit of a Morton code encodes.
+fn bit_axis(bit: u32) -> Axis {
+    match bit % 3 {
+        2 => Axis::X,
+        1 => Axis::Y,
+        _ => Axis::Z,
+    }
+}
+
+/// Sorts by code and breaks ties by input order, so the tree does not
+/// depend on the number of threads. Chunks are sorted in parallel and then
+/// merged pairwise.
+fn parallel_sort(primitives: &mut Vec<Primitive>, threads: usize) {
+    let key = |p: &Primitive| (p.code, p.index);
+    let mut width = primitives.len().div_ceil(threads).max(PARALLEL_GRAIN);
+    thread::scope(|s| {
+        for c in primitives.chunks_mut(width) {
+            s.spawn(move || c.sort_unstable_by_key(key));
+        }
+    });
+    let mut buffer = primitives.clone();
+    while width < primitives.len() {
+        thread::scope(|s| {
+            for (src, dst) in primitives
+                .chunks(2 * width)
+                .zip(buffer.chunks_mut(2 * width))
+            {
+                s.spawn(move || {
+                    let (a, b) = src.split_at(width.min(src.len()));
+                    let (mut i, mut j) = (0, 0);
+                    for d in dst.iter_mut() {
+                        if j == b.len() || (i < a.len() && key(&a[i]) < key(&b[j])) {
+                            *d = a[i];
+                            i += 1;
+                        } else {
+                            *d = b[j];
+                            j += 1;
+                        }
+                    }
+                });
+            }
+        });
+        std::mem::swap(primitives, &mut buffer);
+        width *= 2;
+    }
+}
+
+/// Builds the subtree over sorted `primitives`, which start at `first` in
+/// the whole array, by splitting where the highest differing bit of their
+/// codes flips. The two halves are independent and built on separate
+/// threads while there are some to spare. Up to `leaf_size` primitives
+/// stay in one leaf when splitting them does not lower the cost.
+fn emit(
+    primitives: &[Primitive],
+    first: usize,
+    leaf_size: usize,
+    depth: usize,
+    threads: usize,
+) -> Box<BuildNode> {
+    let diff = primitives[0].code ^ primitives[primitives.len() - 1].code;
+    if primitives.len() == 1 || (diff == 0 && primitives.len() <= leaf_size) {
+        return BuildNode::leaf(primitives, first);
+    }
+    let (axis, mid) = if diff == 0 || depth >= MEDIAN_DEPTH {
+        // Centroids too close for the codes to tell apart, or a chain of
+        // lopsided splits that would get too deep to traverse.
+        (Axis::X, primitives.len() / 2)
+    } else {
+        let bit = 63 - diff.leading_zeros();
+        let mid = primitives.partition_point(|p| p.code & (1 << bit) == 0);
+        (bit_axis(bit), mid)
+    };
+    let (left, right) = primitives.split_at(mid);
+    let (left, right) = if threads > 1 && primitives.len() >= PARALLEL_GRAIN {
+        thread::scope(|s| {
+            let l = s.spawn(|| emit(left, first, leaf_size, depth + 1, threads / 2));
+            let r = emit(
+                right,
+                first + mid,
+                leaf_size,
+                depth + 1,
+                threads - threads / 2,
+            );
+            (l.join().unwrap(), r)
+        })
+    } else {
+        (
+            emit(left, first, leaf_size, depth + 1, 1),
+            emit(right, first + mid, leaf_size, depth + 1, 1),
+        )
+    };
+    let node = BuildNode::interior(axis, left, right);
+    if primitives.len() <= leaf_size {
+        let leaf = BuildNode::leaf(primitives, first);
+        if leaf.cost <= node.cost {
+            return leaf;
+        }
+    }
+    node
+}
+
+/// Restructures every treelet bottom up, children before their parents.
+/// Subtrees too small to fill a treelet gain little and are left alone.
+fn optimize(node: Box<BuildNode>, threads: usize) -> Box<BuildNode> {
+    let size = node.size;
+    if size < TREELET_SIZE {
+        return node;
+    }
+    let (left, right) = match node.kind {
+        Kind::Leaf(_) => return node,
+        Kind::Interior(_, left, right) => (left, right),
+    };
+    let (left, right) = if threads > 1 && size >= PARALLEL_GRAIN {
+        thread::scope(|s| {
+            let l = s.spawn(|| optimize(left, threads / 2));
+            let r = optimize(right, threads - threads / 2);
+            (l.join().unwrap(), r)
+        })
+    } else {
+        (optimize(left, 1), optimize(right, 1))
+    };
+    restructure(left, right)
+}
+
+/// Grows a treelet under the node with children `left` and `right` by
+/// repeatedly opening its largest leaf, then finds the binary tree over the
+/// treelet's leaves with the lowest cost by trying every partition of
+/// every subset of them.
+fn restructure(left: Box<BuildNode>, right: Box<BuildNode>) -> Box<BuildNode> {
+    let mut leaves = vec![left, right];
+    while leaves.len() < TREELET_SIZE {
+        let mut largest: Option<usize> = None;
+        for (i, l) in leaves.iter().enumerate() {
+            if let Kind::Interior(..) = l.kind {
+                if largest
+                    .is_none_or(|j| l.bbox.get_surface_area() > leaves[j].bbox.get_surface_area())
+                {
+                    largest = Some(i);
+                }
+            }
+        }
+        let opened = match largest {
+            Some(i) => leaves.swap_remove(i),
+            None => break,
+        };
+        if let Kind::Interior(_, a, b) = opened.kind {
+            leaves.push(a);
+            leaves.push(b);
+        }
+    }
+    let full = (1usize << leaves.len()) - 1;
+    let mut boxes = [AABBox3::new(); 1 << TREELET_SIZE];
+    let mut costs = [0.0; 1 << TREELET_SIZE];
+    let mut splits = [0usize; 1 << TREELET_SIZE];
+    // Every proper subset has a lower value than its superset, so it is
+    // solved first. Only halves holding the lowest member are tried, the
+    // mirrored partitions cost the same.
+    for set in 1..=full {
+        let lowest = set & set.wrapping_neg();
+        let leaf = &leaves[lowest.trailing_zeros() as usize];
+        boxes[set] = boxes[set ^ lowest];
+        ExpandableToOther::expand(&mut boxes[set], &leaf.bbox);
+        if set == lowest {
+            costs[set] = leaf.cost;
+            continue;
+        }
+        let rest = set ^ lowest;
+        costs[set] = Real::MAX;
+        let mut others = rest;
+        loop {
+            let part = lowest | others;
+            if part != set {
+                let cost = costs[part] + costs[set ^ part];
+                if cost < costs[set] {
+                    costs[set] = cost;
+                    splits[set] = part;
+                }
+            }
+            if others == 0 {
+                break;
+            }
+            others = (others - 1) & rest;
+        }
+        costs[set] += TRAVERSAL_COST * boxes[set].get_surface_area();
+    }
+    let mut slots: Vec<Option<Box<BuildNode>>> = leaves.into_iter().map(Some).collect();
+    assemble(full, &splits, &mut slots)
+}
+
+fn assemble(set: usize, splits: &[usize], slots: &mut [Option<Box<BuildNode>>]) -> Box<BuildNode> {
+    if set.count_ones() == 1 {
+        return slots[set.trailing_zeros() as usize].take().unwrap();
+    }
+    let a = assemble(splits[set], splits, slots);
+    let b = assemble(set ^ splits[set], splits, slots);
+    BuildNode::interior_ordered(a, b)
+}
+
+/// Lays the tree out depth first, the layout `Bvh` traverses.
+fn flatten(node: &BuildNode, nodes: &mut Vec<Node>) {
+    let index = nodes.len();
+    match node.kind {
+        Kind::Leaf(first) => nodes.push(Node {
+            bbox: node.bbox,
+            offset: first,
+            count: node.size,
+            axis: Axis::X,
+        }),
+        Kind::Interior(axis, ref first, ref second) => {
+            nodes.push(Node {
+                bbox: node.bbox,
+                offset: 0,
+                count: 0,
+                axis,
+            });
+            flatten(first, nodes);
+            nodes[index].offset = nodes.len();
+            flatten(second, nodes);
+        }
+    }
+}
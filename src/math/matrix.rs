@@ -1,28 +1,147 @@
 use std::ops::{Mul, MulAssign};
 
-use super::vector::Vec3;
+use super::super::io::file::Stream;
+use super::float::gamma;
+use super::quaternion::Quaternion;
+use super::ray::Ray3;
+use super::vector::{Axis, Vec3};
+use super::Real;
+
+/// Polar decomposition stops when an iteration changes no element by more
+/// than this.
+const DECOMPOSITION_TOLERANCE: Real = 1e-6;
+const DECOMPOSITION_MAX_STEPS: usize = 100;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Mat4x4 {
-    pub data: [[f64; 4]; 4],
+    pub data: [[Real; 4]; 4],
 }
 
 impl Mat4x4 {
     pub fn new() -> Mat4x4 {
         Mat4x4 {
             data: [
-                [1f64, 0f64, 0f64, 0f64],
-                [0f64, 1f64, 0f64, 0f64],
-                [0f64, 0f64, 1f64, 0f64],
-                [0f64, 0f64, 0f64, 1f64],
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
             ],
         }
     }
 
-    pub fn rotation_transform(d: f64, v: &Vec3) -> Mat4x4 {
-        let sinus: f64 = d.sin();
-        let cosinus: f64 = d.cos();
-        let oneminuscos = 1f64 - cosinus;
+    /// Reads sixteen single precision floats stored column by column.
+    pub fn read(&mut self, s: &mut Stream) {
+        for j in 0..4 {
+            for i in 0..4 {
+                self.data[i][j] = s.read(&0f32) as Real;
+            }
+        }
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, None for singular
+    /// matrices.
+    pub fn inverse(&self) -> Option<Mat4x4> {
+        let mut a = self.data;
+        let mut inv = Mat4x4::new().data;
+        for c in 0..4 {
+            let mut pivot = c;
+            for r in (c + 1)..4 {
+                if a[r][c].abs() > a[pivot][c].abs() {
+                    pivot = r;
+                }
+            }
+            if a[pivot][c].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(c, pivot);
+            inv.swap(c, pivot);
+            let d = 1.0 / a[c][c];
+            for j in 0..4 {
+                a[c][j] *= d;
+                inv[c][j] *= d;
+            }
+            for r in 0..4 {
+                if r == c {
+                    continue;
+                }
+                let f = a[r][c];
+                if f == 0.0 {
+                    continue;
+                }
+                for j in 0..4 {
+                    a[r][j] -= f * a[c][j];
+                    inv[r][j] -= f * inv[c][j];
+                }
+            }
+        }
+        Some(Mat4x4 { data: inv })
+    }
+
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        *self * *p
+    }
+
+    /// Transforms a point known up to `p_error` per axis and returns it with
+    /// the error bound of the result, for an affine matrix.
+    pub fn transform_point_with_error(&self, p: &Vec3, p_error: &Vec3) -> (Vec3, Vec3) {
+        let mut error = Vec3::new();
+        for i in 0..3 {
+            let m = &self.data[i];
+            let rounding = m[0].abs() * p.x.abs()
+                + m[1].abs() * p.y.abs()
+                + m[2].abs() * p.z.abs()
+                + m[3].abs();
+            let carried = m[0].abs() * p_error.x + m[1].abs() * p_error.y + m[2].abs() * p_error.z;
+            error[Axis::ALL[i]] = gamma(3) * rounding + (1.0 + gamma(3)) * carried;
+        }
+        (self.transform_point(p), error)
+    }
+
+    /// Ignores the translation part.
+    pub fn transform_direction(&self, d: &Vec3) -> Vec3 {
+        Vec3 {
+            x: self.data[0][0] * d.x + self.data[0][1] * d.y + self.data[0][2] * d.z,
+            y: self.data[1][0] * d.x + self.data[1][1] * d.y + self.data[1][2] * d.z,
+            z: self.data[2][0] * d.x + self.data[2][1] * d.y + self.data[2][2] * d.z,
+        }
+    }
+
+    /// Normals go through the inverse transpose, so this must be called on the
+    /// inverse of the matrix that transforms the surface.
+    pub fn transform_normal(&self, n: &Vec3) -> Vec3 {
+        Vec3 {
+            x: self.data[0][0] * n.x + self.data[1][0] * n.y + self.data[2][0] * n.z,
+            y: self.data[0][1] * n.x + self.data[1][1] * n.y + self.data[2][1] * n.z,
+            z: self.data[0][2] * n.x + self.data[1][2] * n.y + self.data[2][2] * n.z,
+        }
+    }
+
+    /// The direction is not normalized, so distances along the ray and its
+    /// interval stay the same in both spaces.
+    pub fn transform_ray(&self, r: &Ray3) -> Ray3 {
+        Ray3::with_interval(
+            self.transform_point(&r.o),
+            self.transform_direction(&r.d),
+            r.t_min,
+            r.t_max,
+        )
+        .with_time(r.time)
+    }
+
+    /// Like `transform_ray`, also returns the distance along the new ray
+    /// that its rounded origin is uncertain by. Hits closer than that may be
+    /// the surface the ray left, so they must be skipped.
+    pub fn transform_ray_with_error(&self, r: &Ray3) -> (Ray3, Real) {
+        let (_, o_error) = self.transform_point_with_error(&r.o, &Vec3::new());
+        let ray = self.transform_ray(r);
+        let t_error = ray.d.abs().dot(&o_error) / ray.d.squared_length();
+        (ray, t_error)
+    }
+
+    pub fn rotation_transform(d: Real, v: &Vec3) -> Mat4x4 {
+        let sinus: Real = d.sin();
+        let cosinus: Real = d.cos();
+        let oneminuscos = 1.0 - cosinus;
         let w = v;
         let wx2 = w.x * w.x;
         let wxy = w.x * w.y;
@@ -60,17 +179,114 @@ impl Mat4x4 {
             ],
         }
     }
+
+    pub fn translation(v: &Vec3) -> Mat4x4 {
+        let mut m = Mat4x4::new();
+        m.data[0][3] = v.x;
+        m.data[1][3] = v.y;
+        m.data[2][3] = v.z;
+        m
+    }
+
+    pub fn scale(v: &Vec3) -> Mat4x4 {
+        let mut m = Mat4x4::new();
+        m.data[0][0] = v.x;
+        m.data[1][1] = v.y;
+        m.data[2][2] = v.z;
+        m
+    }
+
+    /// Maps world space to the space of a viewer at `location` looking at
+    /// `target`, with the same axes as the camera: x to the right, y up and
+    /// z toward the target. Its inverse places an object that way.
+    pub fn look_at(location: &Vec3, target: &Vec3, up: &Vec3) -> Mat4x4 {
+        let z = (target - location).normalized();
+        let x = z.cross(up).normalized();
+        let y = x.cross(&z);
+        Mat4x4 {
+            data: [
+                [x.x, x.y, x.z, -x.dot(location)],
+                [y.x, y.y, y.z, -y.dot(location)],
+                [z.x, z.y, z.z, -z.dot(location)],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn transpose(&self) -> Mat4x4 {
+        let mut m = *self;
+        for i in 0..4 {
+            for j in 0..4 {
+                m.data[i][j] = self.data[j][i];
+            }
+        }
+        m
+    }
+
+    /// Splits an affine transform into translation, rotation and what is
+    /// left of the upper 3x3 part (scale and shear), so that
+    /// `self = translation * rotation * rest`. The rotation comes from the
+    /// polar decomposition (Shoemake and Duff 1992), transforms that mirror
+    /// are not supported.
+    pub fn decompose(&self) -> (Vec3, Quaternion, Mat4x4) {
+        let translation = Vec3 {
+            x: self.data[0][3],
+            y: self.data[1][3],
+            z: self.data[2][3],
+        };
+        let mut m = *self;
+        for i in 0..3 {
+            m.data[i][3] = 0.0;
+            m.data[3][i] = 0.0;
+        }
+        m.data[3][3] = 1.0;
+        // Averaging with the inverse transpose converges to the closest
+        // orthonormal matrix.
+        let mut r = m;
+        for _ in 0..DECOMPOSITION_MAX_STEPS {
+            let it = match r.inverse() {
+                Some(i) => i.transpose(),
+                None => break,
+            };
+            let mut next = r;
+            let mut change: Real = 0.0;
+            for i in 0..3 {
+                for j in 0..3 {
+                    next.data[i][j] = 0.5 * (r.data[i][j] + it.data[i][j]);
+                    change = change.max((next.data[i][j] - r.data[i][j]).abs());
+                }
+            }
+            r = next;
+            if change < DECOMPOSITION_TOLERANCE {
+                break;
+            }
+        }
+        let rest = r.transpose() * m;
+        (translation, Quaternion::from_mat4x4(&r), rest)
+    }
+}
+
+impl Default for Mat4x4 {
+    fn default() -> Self {
+        Mat4x4::new()
+    }
 }
 
 impl Mul<Vec3> for Mat4x4 {
     type Output = Vec3;
     fn mul(self, o: Vec3) -> Vec3 {
         Vec3 {
-            x: self.data[0][0] * o.x + self.data[0][1] * o.y + self.data[0][2] * o.z
+            x: self.data[0][0] * o.x
+                + self.data[0][1] * o.y
+                + self.data[0][2] * o.z
                 + self.data[0][3],
-            y: self.data[1][0] * o.x + self.data[1][1] * o.y + self.data[1][2] * o.z
+            y: self.data[1][0] * o.x
+                + self.data[1][1] * o.y
+                + self.data[1][2] * o.z
                 + self.data[1][3],
-            z: self.data[2][0] * o.x + self.data[2][1] * o.y + self.data[2][2] * o.z
+            z: self.data[2][0] * o.x
+                + self.data[2][1] * o.y
+                + self.data[2][2] * o.z
                 + self.data[2][3],
         }
     }
@@ -109,3 +325,84 @@ impl<'a> MulAssign<&'a Mat4x4> for Mat4x4 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: Real = 1e-4;
+
+    fn v(x: Real, y: Real, z: Real) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    fn close(a: &Mat4x4, b: &Mat4x4) -> bool {
+        for i in 0..4 {
+            for j in 0..4 {
+                if (a.data[i][j] - b.data[i][j]).abs() > EPSILON {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn rotation() -> Mat4x4 {
+        Mat4x4::rotation_transform(2.3, &v(1.0, 2.0, -0.5).normalized())
+    }
+
+    #[test]
+    fn inverses_undo_the_transform() {
+        let transforms = [
+            Mat4x4::new(),
+            Mat4x4::translation(&v(1.0, -2.0, 3.0)),
+            Mat4x4::scale(&v(2.0, 0.5, -3.0)),
+            rotation(),
+            Mat4x4::translation(&v(4.0, 0.0, -1.0)) * rotation() * Mat4x4::scale(&v(0.1, 3.0, 2.0)),
+            Mat4x4::look_at(&v(1.0, 2.0, 3.0), &v(0.0, 0.5, 0.0), &v(0.0, 1.0, 0.0)),
+        ];
+        for m in transforms.iter() {
+            let inverse = m.inverse().unwrap();
+            assert!(close(&(*m * inverse), &Mat4x4::new()));
+            assert!(close(&(inverse * *m), &Mat4x4::new()));
+        }
+        // The rows have to be swapped to find a pivot.
+        let mut swap = Mat4x4::new();
+        swap.data[0] = [0.0, 1.0, 0.0, 0.0];
+        swap.data[1] = [1.0, 0.0, 0.0, 0.0];
+        assert!(close(&(swap * swap.inverse().unwrap()), &Mat4x4::new()));
+    }
+
+    #[test]
+    fn singular_matrices_have_no_inverse() {
+        assert!(Mat4x4::scale(&v(1.0, 0.0, 1.0)).inverse().is_none());
+        let mut m = rotation();
+        m.data[2] = m.data[0];
+        assert!(m.inverse().is_none());
+    }
+
+    #[test]
+    fn decompose_splits_translation_rotation_and_scale() {
+        let translation = v(4.0, -1.0, 0.5);
+        let scale = Mat4x4::scale(&v(0.5, 2.0, 3.0));
+        let m = Mat4x4::translation(&translation) * rotation() * scale;
+        let (t, q, rest) = m.decompose();
+        assert!((&t - &translation).length() < EPSILON);
+        assert!(close(&q.to_mat4x4(), &rotation()));
+        assert!(close(&rest, &scale));
+        assert!(close(&(Mat4x4::translation(&t) * q.to_mat4x4() * rest), &m));
+    }
+
+    #[test]
+    fn decompose_keeps_the_shear_in_the_rest() {
+        let mut shear = Mat4x4::new();
+        shear.data[0][1] = 0.4;
+        shear.data[1][2] = -0.3;
+        let m = rotation() * shear;
+        let (t, q, rest) = m.decompose();
+        assert!(t.length() < EPSILON);
+        assert!(close(&(q.to_mat4x4() * rest), &m));
+        // The rest of a polar decomposition is symmetric.
+        assert!(close(&rest, &rest.transpose()));
+    }
+}
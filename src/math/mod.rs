@@ -1,7 +1,36 @@
-// pub mod aabbox;
-// pub mod kdtree;
-// pub mod matrix;
+pub mod aabbox;
+pub mod boxes;
+pub mod bvh;
+pub mod cone;
+pub mod cylinder;
+pub mod disk;
+pub mod distribution;
+pub mod float;
+pub mod kdtree;
+pub mod lbvh;
+pub mod matrix;
+pub mod onb;
+pub mod plane;
+pub mod polynomial;
+pub mod quad;
+pub mod quaternion;
 pub mod ray;
+pub mod sampling;
 pub mod sphere;
-// pub mod triangle;
+pub mod tessellator;
+pub mod torus;
+pub mod triangle;
 pub mod vector;
+
+/// Scalar type of the whole engine, single precision trades accuracy for
+/// memory and speed on large scenes.
+#[cfg(not(feature = "single-precision"))]
+pub type Real = f64;
+#[cfg(feature = "single-precision")]
+pub type Real = f32;
+
+#[cfg(feature = "single-precision")]
+pub use std::f32::consts;
+/// Mathematical constants in `Real` precision.
+#[cfg(not(feature = "single-precision"))]
+pub use std::f64::consts;
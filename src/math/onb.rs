@@ -0,0 +1,31 @@
+use super::vector::Vec3;
+
+/// Orthonormal basis around a unit vector `w`, moves directions sampled
+/// around +z in a local frame to world space and back.
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    /// `w` must be a unit vector, `u` and `v` are picked so the basis is
+    /// right handed.
+    pub fn from_w(w: &Vec3) -> Self {
+        let (u, v) = w.get_perpendiculars();
+        Self { u, v, w: *w }
+    }
+
+    pub fn local_to_world(&self, a: &Vec3) -> Vec3 {
+        &(&(&self.u * a.x) + &(&self.v * a.y)) + &(&self.w * a.z)
+    }
+
+    pub fn world_to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3 {
+            x: a.dot(&self.u),
+            y: a.dot(&self.v),
+            z: a.dot(&self.w),
+        }
+    }
+}
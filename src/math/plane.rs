@@ -0,0 +1,83 @@
+use super::super::render::hit::{Hitable, Info as HitInfo};
+use super::super::render::material::Material;
+use super::aabbox::AABBox3;
+use super::float::gamma;
+use super::ray::Ray3;
+use super::vector::{Vec2, Vec3};
+use super::Real;
+
+/// Infinite plane, the uv coordinates are distances from `point` along two
+/// tangents so textures repeat on it.
+pub struct Plane {
+    point: Vec3,
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    pub material: Box<dyn Material>,
+}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3, material: Box<dyn Material>) -> Self {
+        let normal = normal.normalized();
+        let (tangent, bitangent) = normal.get_perpendiculars();
+        Plane {
+            point,
+            normal,
+            tangent,
+            bitangent,
+            material,
+        }
+    }
+
+    pub fn get_point(&self) -> &Vec3 {
+        &self.point
+    }
+
+    pub fn get_normal(&self) -> &Vec3 {
+        &self.normal
+    }
+}
+
+/// The point `c.x` along `tangent` and `c.y` along `bitangent` from
+/// `origin` with the bound of its rounding error. Rebuilding hit points
+/// from their planar coordinates puts them on the plane whatever error the
+/// ray distance has.
+pub fn point_on_plane(origin: &Vec3, tangent: &Vec3, bitangent: &Vec3, c: &Vec2) -> (Vec3, Vec3) {
+    let a = tangent * c.x;
+    let b = bitangent * c.y;
+    let p = &(origin + &a) + &b;
+    let p_error = &(&(&origin.abs() + &a.abs()) + &b.abs()) * gamma(3);
+    (p, p_error)
+}
+
+impl Hitable for Plane {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        let denom = self.normal.dot(&r.d);
+        if denom == 0.0 {
+            return None;
+        }
+        let t = self.normal.dot(&(&self.point - &r.o)) / denom;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+        let local = &r.point_at_parameter(t) - &self.point;
+        let uv = Vec2 {
+            x: local.dot(&self.tangent),
+            y: local.dot(&self.bitangent),
+        };
+        let (p, p_error) = point_on_plane(&self.point, &self.tangent, &self.bitangent, &uv);
+        Some(HitInfo {
+            t,
+            p,
+            p_error,
+            n: self.normal,
+            ng: self.normal,
+            uv,
+            m: self.material.as_ref(),
+        })
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        None
+    }
+}
@@ -0,0 +1,178 @@
+use super::consts::PI;
+use super::Real;
+
+/// Coefficients closer to zero than this are treated as zero.
+const EPSILON: Real = 1e-9;
+
+/// Newton steps applied to the closed form quartic roots, which lose many
+/// digits to cancellation.
+const POLISH_STEPS: usize = 2;
+
+/// Real roots of `c[0] + c[1] x + c[2] x^2`, unsorted, with their count.
+pub fn solve_quadratic(c: &[Real; 3]) -> ([Real; 2], usize) {
+    let mut roots = [0.0; 2];
+    let p = c[1] / (2.0 * c[2]);
+    let q = c[0] / c[2];
+    let d = p * p - q;
+    if d.abs() < EPSILON {
+        roots[0] = -p;
+        return (roots, 1);
+    }
+    if d < 0.0 {
+        return (roots, 0);
+    }
+    let sqrt_d = d.sqrt();
+    roots[0] = sqrt_d - p;
+    roots[1] = -sqrt_d - p;
+    (roots, 2)
+}
+
+/// Real roots of `c[0] + c[1] x + c[2] x^2 + c[3] x^3`, unsorted, with their
+/// count (Schwarze, Graphics Gems 1990).
+pub fn solve_cubic(c: &[Real; 4]) -> ([Real; 3], usize) {
+    let mut roots = [0.0; 3];
+    // Normal form x^3 + a x^2 + b x + c, then x = y - a / 3 removes the
+    // quadratic term and leaves y^3 + 3 p y + 2 q.
+    let a = c[2] / c[3];
+    let b = c[1] / c[3];
+    let cc = c[0] / c[3];
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + cc) / 2.0;
+    let cb_p = p * p * p;
+    let d = q * q + cb_p;
+    let count = if d.abs() < EPSILON {
+        if q.abs() < EPSILON {
+            roots[0] = 0.0;
+            1
+        } else {
+            let u = (-q).cbrt();
+            roots[0] = 2.0 * u;
+            roots[1] = -u;
+            2
+        }
+    } else if d < 0.0 {
+        // Three real roots.
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        roots[0] = t * phi.cos();
+        roots[1] = -t * (phi + PI / 3.0).cos();
+        roots[2] = -t * (phi - PI / 3.0).cos();
+        3
+    } else {
+        let sqrt_d = d.sqrt();
+        roots[0] = (sqrt_d - q).cbrt() - (sqrt_d + q).cbrt();
+        1
+    };
+    let sub = a / 3.0;
+    for r in roots.iter_mut().take(count) {
+        *r -= sub;
+    }
+    (roots, count)
+}
+
+/// Real roots of `c[0] + c[1] x + ... + c[4] x^4`, unsorted, with their count
+/// (Ferrari's method as in Schwarze, Graphics Gems 1990).
+pub fn solve_quartic(c: &[Real; 5]) -> ([Real; 4], usize) {
+    let mut roots = [0.0; 4];
+    let mut count = 0;
+    // Normal form x^4 + a x^3 + b x^2 + c x + d, then x = y - a / 4 removes
+    // the cubic term and leaves y^4 + p y^2 + q y + r.
+    let a = c[3] / c[4];
+    let b = c[2] / c[4];
+    let cc = c[1] / c[4];
+    let d = c[0] / c[4];
+    let sq_a = a * a;
+    let p = -3.0 / 8.0 * sq_a + b;
+    let q = sq_a * a / 8.0 - a * b / 2.0 + cc;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * b / 16.0 - a * cc / 4.0 + d;
+    // With y = k x the roots x are about one in size, which makes the
+    // absolute thresholds relative to the roots.
+    let k = p
+        .abs()
+        .sqrt()
+        .max(q.abs().cbrt())
+        .max(r.abs().sqrt().sqrt());
+    let k = if k > 0.0 { k } else { 1.0 };
+    let (p, q, r) = (p / (k * k), q / (k * k * k), r / (k * k * k * k));
+    if r.abs() < EPSILON {
+        // y (y^3 + p y + q) = 0
+        let (s, n) = solve_cubic(&[q, p, 0.0, 1.0]);
+        roots[..n].copy_from_slice(&s[..n]);
+        roots[n] = 0.0;
+        count = n + 1;
+    } else {
+        // One root of the resolvent cubic splits the quartic in two quadratics.
+        let (s, _) = solve_cubic(&[r * p / 2.0 - q * q / 8.0, -r, -p / 2.0, 1.0]);
+        let z = s[0];
+        // Both vanish for double roots and v for even quartics, where
+        // rounding alone may take them below zero. What is left of their
+        // terms then is noise, which with the resolvent root's own error
+        // makes for about half the digits.
+        let tolerance = Real::EPSILON.sqrt();
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if u.abs() <= tolerance * (z * z + r.abs()) {
+            0.0
+        } else if u > 0.0 {
+            u.sqrt()
+        } else {
+            return (roots, 0);
+        };
+        let v = if v.abs() <= tolerance * (2.0 * z.abs() + p.abs()) {
+            0.0
+        } else if v > 0.0 {
+            v.sqrt()
+        } else {
+            return (roots, 0);
+        };
+        let v = if q < 0.0 { -v } else { v };
+        for quadratic in [[z - u, v, 1.0], [z + u, -v, 1.0]].iter() {
+            let (s, n) = solve_quadratic(quadratic);
+            roots[count..count + n].copy_from_slice(&s[..n]);
+            count += n;
+        }
+    }
+    let sub = a / 4.0;
+    for x in roots.iter_mut().take(count) {
+        *x = *x * k - sub;
+        for _ in 0..POLISH_STEPS {
+            let f = (((c[4] * *x + c[3]) * *x + c[2]) * *x + c[1]) * *x + c[0];
+            let df = ((4.0 * c[4] * *x + 3.0 * c[3]) * *x + 2.0 * c[2]) * *x + c[1];
+            if df != 0.0 {
+                *x -= f / df;
+            }
+        }
+    }
+    (roots, count)
+}
+
+/// Real roots of `c[0] + c[1] x + ... + c[4] x^4` whose leading coefficients
+/// may vanish, terms negligible next to the largest one are dropped before
+/// picking the solver of the remaining degree.
+pub fn solve_polynomial(c: &[Real; 5]) -> ([Real; 4], usize) {
+    let mut roots = [0.0; 4];
+    let scale = c.iter().fold(0.0, |m: Real, x| m.max(x.abs()));
+    let degree = match c.iter().rposition(|x| x.abs() > scale * Real::EPSILON) {
+        Some(d) => d,
+        None => return (roots, 0),
+    };
+    match degree {
+        0 => (roots, 0),
+        1 => {
+            roots[0] = -c[0] / c[1];
+            (roots, 1)
+        }
+        2 => {
+            let (s, n) = solve_quadratic(&[c[0], c[1], c[2]]);
+            roots[..2].copy_from_slice(&s);
+            (roots, n)
+        }
+        3 => {
+            let (s, n) = solve_cubic(&[c[0], c[1], c[2], c[3]]);
+            roots[..3].copy_from_slice(&s);
+            (roots, n)
+        }
+        _ => solve_quartic(c),
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use super::super::render::hit::{Hitable, Info as HitInfo};
+use super::super::render::light::{AreaLight, Light};
+use super::super::render::material::Material;
+use super::aabbox::{AABBox3, ExpandableToPoint3};
+use super::float::gamma;
+use super::ray::Ray3;
+use super::vector::{Vec2, Vec3};
+use super::Real;
+
+/// Parallelogram spanned by the edges `u` and `v` from the corner `q`,
+/// a rectangle when the edges are perpendicular. The normal is `u` x `v`
+/// and the uv coordinates go from 0 to 1 along the edges.
+pub struct Quad {
+    q: Vec3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    /// `u` x `v` over its squared length, maps a point of the plane to its
+    /// edge coordinates.
+    w: Vec3,
+    pub material: Box<dyn Material>,
+}
+
+impl Quad {
+    pub fn new(q: Vec3, u: Vec3, v: Vec3, material: Box<dyn Material>) -> Self {
+        let n = u.cross(&v);
+        Quad {
+            q,
+            u,
+            v,
+            normal: n.normalized(),
+            w: &n / n.dot(&n),
+            material,
+        }
+    }
+
+    pub fn get_corner(&self) -> &Vec3 {
+        &self.q
+    }
+
+    pub fn get_edges(&self) -> (&Vec3, &Vec3) {
+        (&self.u, &self.v)
+    }
+
+    pub fn get_normal(&self) -> &Vec3 {
+        &self.normal
+    }
+
+    pub fn get_area(&self) -> Real {
+        self.u.cross(&self.v).length()
+    }
+
+    /// Hit information of the point at the edge coordinates (`alpha`,
+    /// `beta`) found at distance `t`.
+    pub fn get_hit_info(&self, t: Real, alpha: Real, beta: Real) -> HitInfo<'_> {
+        let a = &self.u * alpha;
+        let b = &self.v * beta;
+        HitInfo {
+            t,
+            // Rebuilt from the edge coordinates so it lies on the quad.
+            p: &(&self.q + &a) + &b,
+            p_error: &(&(&self.q.abs() + &a.abs()) + &b.abs()) * gamma(3),
+            n: self.normal,
+            ng: self.normal,
+            uv: Vec2 { x: alpha, y: beta },
+            m: self.material.as_ref(),
+        }
+    }
+}
+
+impl Hitable for Quad {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        let denom = self.normal.dot(&r.d);
+        if denom == 0.0 {
+            return None;
+        }
+        let t = self.normal.dot(&(&self.q - &r.o)) / denom;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+        let p = r.point_at_parameter(t);
+        let planar = &p - &self.q;
+        let alpha = self.w.dot(&planar.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        Some(self.get_hit_info(t, alpha, beta))
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        let mut b = AABBox3::new();
+        b.expand(&self.q);
+        b.expand(&(&self.q + &self.u));
+        b.expand(&(&self.q + &self.v));
+        b.expand(&(&(&self.q + &self.u) + &self.v));
+        Some(b)
+    }
+
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Light>>) {
+        if let Some(emission) = self.material.get_emission() {
+            lights.push(Arc::new(AreaLight::new(Quad::new(
+                self.q,
+                self.u,
+                self.v,
+                Box::new(emission.clone()),
+            ))));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::render::material::Lambertian;
+    use super::*;
+
+    fn v(x: Real, y: Real, z: Real) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    fn quad() -> Quad {
+        Quad::new(
+            v(-1.0, 0.5, 0.0),
+            v(2.0, 0.0, 0.5),
+            v(0.3, 1.5, 0.0),
+            Box::new(Lambertian::new(Vec3::new())),
+        )
+    }
+
+    #[test]
+    fn rays_toward_the_quad_hit_it_at_their_edge_coordinates() {
+        let quad = quad();
+        for i in 0..5 {
+            for j in 0..5 {
+                let (alpha, beta) = (i as Real * 0.2 + 0.1, j as Real * 0.2 + 0.1);
+                let target = &(&quad.q + &(&quad.u * alpha)) + &(&quad.v * beta);
+                for side in [1.0, -1.0].iter() {
+                    let o = &(&target + &(&quad.normal * (2.0 * side))) + &(&quad.u * 0.3);
+                    let r = Ray3::new(o, &target - &o);
+                    let rec = quad.hit(&r, 0.0, Real::MAX).unwrap();
+                    assert!((&rec.p - &target).length() < 1e-4);
+                    assert!((&r.point_at_parameter(rec.t) - &rec.p).length() < 1e-4);
+                    assert!((&rec.n - &quad.normal).length() < 1e-4);
+                    assert!((rec.uv.x - alpha).abs() < 1e-4 && (rec.uv.y - beta).abs() < 1e-4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rays_beside_or_along_the_quad_miss() {
+        let quad = quad();
+        let down = -&quad.normal;
+        for (alpha, beta) in [(1.01, 0.5), (0.5, -0.01), (-0.01, 0.5), (0.5, 1.01)].iter() {
+            let beside = &(&quad.q + &(&quad.u * *alpha)) + &(&quad.v * *beta);
+            assert!(quad
+                .hit(&Ray3::new(&beside + &quad.normal, down), 0.0, Real::MAX)
+                .is_none());
+        }
+        let along = Ray3::new(&quad.q - &quad.u, quad.u);
+        assert!(quad.hit(&along, 0.0, Real::MAX).is_none());
+    }
+}
@@ -0,0 +1,318 @@
+use std::ops::Mul;
+
+use super::matrix::Mat4x4;
+use super::vector::Vec3;
+use super::Real;
+
+/// Rotation quaternion, `w` is the scalar part.
+#[derive(Debug, Clone, Copy)]
+pub struct Quaternion {
+    pub w: Real,
+    pub x: Real,
+    pub y: Real,
+    pub z: Real,
+}
+
+impl Mul<&Quaternion> for &Quaternion {
+    type Output = Quaternion;
+    /// The result rotates by `o` first and then by `self`.
+    fn mul(self, o: &Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+            x: self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            y: self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            z: self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+        }
+    }
+}
+
+impl Quaternion {
+    /// The identity rotation.
+    pub fn new() -> Self {
+        Quaternion {
+            w: 1.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    /// Rotation of `d` radians around the unit axis `v`, in the same
+    /// direction as `Mat4x4::rotation_transform`.
+    pub fn from_axis_angle(d: Real, v: &Vec3) -> Self {
+        let half = d * 0.5;
+        let s = half.sin();
+        Quaternion {
+            w: half.cos(),
+            x: v.x * s,
+            y: v.y * s,
+            z: v.z * s,
+        }
+    }
+
+    /// Extracts the rotation of the upper 3x3 part, which must be orthonormal.
+    pub fn from_mat4x4(m: &Mat4x4) -> Self {
+        let d = &m.data;
+        let trace = d[0][0] + d[1][1] + d[2][2];
+        let q = if trace > 0.0 {
+            let s = 0.5 / (trace + 1.0).sqrt();
+            Quaternion {
+                w: 0.25 / s,
+                x: (d[2][1] - d[1][2]) * s,
+                y: (d[0][2] - d[2][0]) * s,
+                z: (d[1][0] - d[0][1]) * s,
+            }
+        } else if d[0][0] > d[1][1] && d[0][0] > d[2][2] {
+            let s = 2.0 * (1.0 + d[0][0] - d[1][1] - d[2][2]).sqrt();
+            Quaternion {
+                w: (d[2][1] - d[1][2]) / s,
+                x: 0.25 * s,
+                y: (d[0][1] + d[1][0]) / s,
+                z: (d[0][2] + d[2][0]) / s,
+            }
+        } else if d[1][1] > d[2][2] {
+            let s = 2.0 * (1.0 + d[1][1] - d[0][0] - d[2][2]).sqrt();
+            Quaternion {
+                w: (d[0][2] - d[2][0]) / s,
+                x: (d[0][1] + d[1][0]) / s,
+                y: 0.25 * s,
+                z: (d[1][2] + d[2][1]) / s,
+            }
+        } else {
+            let s = 2.0 * (1.0 + d[2][2] - d[0][0] - d[1][1]).sqrt();
+            Quaternion {
+                w: (d[1][0] - d[0][1]) / s,
+                x: (d[0][2] + d[2][0]) / s,
+                y: (d[1][2] + d[2][1]) / s,
+                z: 0.25 * s,
+            }
+        };
+        q.normalized()
+    }
+
+    pub fn to_mat4x4(self) -> Mat4x4 {
+        let (w, x, y, z) = (self.w, self.x, self.y, self.z);
+        Mat4x4 {
+            data: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - w * z),
+                    2.0 * (x * z + w * y),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * y + w * z),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - w * x),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * z - w * y),
+                    2.0 * (y * z + w * x),
+                    1.0 - 2.0 * (x * x + y * y),
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn dot(&self, o: &Self) -> Real {
+        self.w * o.w + self.x * o.x + self.y * o.y + self.z * o.z
+    }
+
+    pub fn length(&self) -> Real {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalized(&self) -> Self {
+        let len = self.length();
+        Quaternion {
+            w: self.w / len,
+            x: self.x / len,
+            y: self.y / len,
+            z: self.z / len,
+        }
+    }
+
+    /// The inverse rotation of a unit quaternion.
+    pub fn conjugate(&self) -> Self {
+        Quaternion {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    pub fn rotate(&self, v: &Vec3) -> Vec3 {
+        let u = Vec3 {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        };
+        let t = &u.cross(v) * 2.0;
+        &(v + &(&t * self.w)) + &u.cross(&t)
+    }
+
+    /// Spherical interpolation along the shortest arc, `t` in [0, 1].
+    pub fn slerp(&self, o: &Self, t: Real) -> Self {
+        let mut cos_theta = self.dot(o);
+        let mut o = *o;
+        if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            o = Quaternion {
+                w: -o.w,
+                x: -o.x,
+                y: -o.y,
+                z: -o.z,
+            };
+        }
+        let (a, b) = if cos_theta > 0.9995 {
+            // Nearly parallel, a linear blend is accurate and avoids 0 / 0.
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (
+                ((1.0 - t) * theta).sin() / sin_theta,
+                (t * theta).sin() / sin_theta,
+            )
+        };
+        Quaternion {
+            w: self.w * a + o.w * b,
+            x: self.x * a + o.x * b,
+            y: self.y * a + o.y * b,
+            z: self.z * a + o.z * b,
+        }
+        .normalized()
+    }
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Quaternion::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: Real = 1e-4;
+
+    /// Rotations around unit axes, with angles past a half turn so every
+    /// branch of `from_mat4x4` is taken.
+    fn rotations() -> Vec<(Real, Vec3)> {
+        let axes = [
+            (1.0, 0.0, 0.0),
+            (0.0, 1.0, 0.0),
+            (0.0, 0.0, 1.0),
+            (1.0, -2.0, 0.5),
+        ];
+        let mut rotations = Vec::new();
+        for &(x, y, z) in axes.iter() {
+            let axis = Vec3 { x, y, z }.normalized();
+            for d in [0.0, 0.3, 1.7, 3.0, 3.1, 4.5, -2.2].iter() {
+                rotations.push((*d, axis));
+            }
+        }
+        rotations
+    }
+
+    fn vectors() -> Vec<Vec3> {
+        vec![
+            Vec3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Vec3 {
+                x: 0.0,
+                y: -2.0,
+                z: 0.5,
+            },
+            Vec3 {
+                x: 0.3,
+                y: 0.7,
+                z: -1.1,
+            },
+        ]
+    }
+
+    /// `q` and `-q` are the same rotation.
+    fn same_rotation(a: &Quaternion, b: &Quaternion) -> bool {
+        (a.dot(b).abs() - 1.0).abs() < EPSILON
+    }
+
+    fn close(a: &Vec3, b: &Vec3) -> bool {
+        (a - b).length() < EPSILON
+    }
+
+    #[test]
+    fn matrices_give_back_the_same_rotation() {
+        for (d, axis) in rotations() {
+            let q = Quaternion::from_axis_angle(d, &axis);
+            let back = Quaternion::from_mat4x4(&q.to_mat4x4());
+            assert!(same_rotation(&q, &back), "{} {:?}", d, axis);
+            let m = Mat4x4::rotation_transform(d, &axis);
+            assert!(same_rotation(&q, &Quaternion::from_mat4x4(&m)));
+        }
+    }
+
+    #[test]
+    fn rotate_agrees_with_the_matrices() {
+        for (d, axis) in rotations() {
+            let q = Quaternion::from_axis_angle(d, &axis);
+            let m = q.to_mat4x4();
+            let r = Mat4x4::rotation_transform(d, &axis);
+            for v in vectors() {
+                let rotated = q.rotate(&v);
+                assert!(close(&rotated, &m.transform_direction(&v)));
+                assert!(close(&rotated, &r.transform_direction(&v)));
+                assert!(close(&q.conjugate().rotate(&rotated), &v));
+            }
+        }
+    }
+
+    #[test]
+    fn products_rotate_by_the_right_one_first() {
+        let rotations = rotations();
+        for (a, b) in rotations.iter().zip(rotations.iter().rev()) {
+            let qa = Quaternion::from_axis_angle(a.0, &a.1);
+            let qb = Quaternion::from_axis_angle(b.0, &b.1);
+            let product = &qa * &qb;
+            for v in vectors() {
+                assert!(close(&product.rotate(&v), &qa.rotate(&qb.rotate(&v))));
+            }
+        }
+    }
+
+    #[test]
+    fn slerp_goes_between_the_ends_along_the_shortest_arc() {
+        let axis = Vec3 {
+            x: 2.0,
+            y: 1.0,
+            z: -1.0,
+        }
+        .normalized();
+        let a = Quaternion::from_axis_angle(0.4, &axis);
+        let b = Quaternion::from_axis_angle(1.6, &axis);
+        assert!(same_rotation(&a.slerp(&b, 0.0), &a));
+        assert!(same_rotation(&a.slerp(&b, 1.0), &b));
+        let quarter = Quaternion::from_axis_angle(0.7, &axis);
+        assert!(same_rotation(&a.slerp(&b, 0.25), &quarter));
+        // The negated end is the same rotation, the path must not change.
+        let negated = Quaternion {
+            w: -b.w,
+            x: -b.x,
+            y: -b.y,
+            z: -b.z,
+        };
+        assert!(same_rotation(&a.slerp(&negated, 0.25), &quarter));
+        // Ends closer than the linear blend threshold.
+        let c = Quaternion::from_axis_angle(0.41, &axis);
+        let middle = Quaternion::from_axis_angle(0.405, &axis);
+        assert!(same_rotation(&a.slerp(&c, 0.5), &middle));
+    }
+}
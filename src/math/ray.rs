@@ -1,20 +1,61 @@
-use super::vector::Vec3;
+use super::vector::{Axis, Vec3};
+use super::Real;
 
+/// A ray with the data every box test needs precomputed, `d` must not be
+/// changed in place, build a new ray instead so `invd` and `sign` follow it.
 #[derive(Debug, Clone, Copy)]
 pub struct Ray3 {
     pub o: Vec3,
     pub d: Vec3,
+    /// Per axis reciprocal of `d`, infinite on the axes `d` is parallel to.
+    pub invd: Vec3,
+    /// 1 on the axes `d` goes toward negative, indexes the near box bound.
+    pub sign: [usize; 3],
+    /// Parametric interval the ray is valid in.
+    pub t_min: Real,
+    pub t_max: Real,
+    /// Moment inside the camera shutter interval the ray travels at.
+    pub time: Real,
 }
 
 impl Ray3 {
     pub fn new(o: Vec3, d: Vec3) -> Self {
+        Self::with_interval(o, d, 0.0, Real::MAX)
+    }
+
+    pub fn with_interval(o: Vec3, d: Vec3, t_min: Real, t_max: Real) -> Self {
+        let invd = Vec3 {
+            x: 1.0 / d.x,
+            y: 1.0 / d.y,
+            z: 1.0 / d.z,
+        };
+        // Reading the sign bit of the reciprocal keeps -0.0 on the negative side.
+        let sign = [
+            invd.x.is_sign_negative() as usize,
+            invd.y.is_sign_negative() as usize,
+            invd.z.is_sign_negative() as usize,
+        ];
         Self {
-            o: o,
-            d: d,
+            o,
+            d,
+            invd,
+            sign,
+            t_min,
+            t_max,
+            time: 0.0,
         }
     }
 
-    pub fn point_at_parameter(&self, t: f64) -> Vec3 { 
+    pub fn with_time(mut self, time: Real) -> Self {
+        self.time = time;
+        self
+    }
+
+    pub fn get_sign(&self, a: Axis) -> usize {
+        self.sign[a as usize]
+    }
+
+    pub fn point_at_parameter(&self, t: Real) -> Vec3 {
         &self.o + &(&self.d * t)
     }
 }
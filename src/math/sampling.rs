@@ -0,0 +1,125 @@
+use super::consts::{FRAC_1_PI, FRAC_PI_2, FRAC_PI_4, PI};
+use super::vector::{Vec2, Vec3};
+use super::Real;
+
+/// Uniform point of the unit disk, concentric so strata of the square stay
+/// compact on the disk (Shirley and Chiu 1997).
+pub fn concentric_disk(u: &Vec2) -> Vec2 {
+    let x = 2.0 * u.x - 1.0;
+    let y = 2.0 * u.y - 1.0;
+    if x == 0.0 && y == 0.0 {
+        return Vec2 { x: 0.0, y: 0.0 };
+    }
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, FRAC_PI_4 * (y / x))
+    } else {
+        (y, FRAC_PI_2 - FRAC_PI_4 * (x / y))
+    };
+    Vec2 {
+        x: r * theta.cos(),
+        y: r * theta.sin(),
+    }
+}
+
+pub fn concentric_disk_pdf() -> Real {
+    FRAC_1_PI
+}
+
+/// Directions of the +z hemisphere with density proportional to the cosine
+/// to +z, what a Lambertian surface scatters. Directions of every warp are
+/// in a local frame around +z, `Onb` moves them to world space, and their
+/// densities are per steradian.
+pub fn cosine_hemisphere(u: &Vec2) -> Vec3 {
+    let d = concentric_disk(u);
+    Vec3 {
+        x: d.x,
+        y: d.y,
+        z: (1.0 - d.x * d.x - d.y * d.y).max(0.0).sqrt(),
+    }
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: Real) -> Real {
+    cos_theta.max(0.0) * FRAC_1_PI
+}
+
+pub fn uniform_hemisphere(u: &Vec2) -> Vec3 {
+    let z = u.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    Vec3 {
+        x: r * phi.cos(),
+        y: r * phi.sin(),
+        z,
+    }
+}
+
+pub fn uniform_hemisphere_pdf() -> Real {
+    0.5 * FRAC_1_PI
+}
+
+pub fn uniform_sphere(u: &Vec2) -> Vec3 {
+    let z = 1.0 - 2.0 * u.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    Vec3 {
+        x: r * phi.cos(),
+        y: r * phi.sin(),
+        z,
+    }
+}
+
+pub fn uniform_sphere_pdf() -> Real {
+    0.25 * FRAC_1_PI
+}
+
+/// Uniform point inside the unit ball, a direction from `u` and the
+/// distance to the center from `r`.
+pub fn uniform_ball(u: &Vec2, r: Real) -> Vec3 {
+    &uniform_sphere(u) * r.cbrt()
+}
+
+/// Uniform direction inside the cone around +z whose half angle has the
+/// cosine `cos_theta_max`, the directions toward a sphere seen from outside.
+pub fn uniform_cone(u: &Vec2, cos_theta_max: Real) -> Vec3 {
+    let z = 1.0 - u.x * (1.0 - cos_theta_max);
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    Vec3 {
+        x: r * phi.cos(),
+        y: r * phi.sin(),
+        z,
+    }
+}
+
+pub fn uniform_cone_pdf(cos_theta_max: Real) -> Real {
+    1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+}
+
+/// Weight of a sample drawn with density `f_pdf` when the same direction
+/// could also come from a strategy with density `g_pdf`, one sample each
+/// (Veach 1997). The weights of both strategies sum to one.
+pub fn power_heuristic(f_pdf: Real, g_pdf: Real) -> Real {
+    let f = f_pdf * f_pdf;
+    let g = g_pdf * g_pdf;
+    if f + g <= 0.0 {
+        return 0.0;
+    }
+    f / (f + g)
+}
+
+/// Picks an index with probability proportional to its term, given the
+/// running sums of non negative terms, by `u.x`, and rescales `u.x` to
+/// [0, 1) inside the term so `u` can be used again.
+pub fn pick_by_sum(sums: &[Real], u: &Vec2) -> (usize, Vec2) {
+    let total = sums[sums.len() - 1];
+    let target = u.x * total;
+    let i = sums.partition_point(|s| *s <= target).min(sums.len() - 1);
+    let start = if i > 0 { sums[i - 1] } else { 0.0 };
+    let width = sums[i] - start;
+    let x = if width > 0.0 {
+        ((target - start) / width).clamp(0.0, 1.0 - Real::EPSILON)
+    } else {
+        0.0
+    };
+    (i, Vec2 { x, y: u.y })
+}
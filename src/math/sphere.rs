@@ -1,43 +1,165 @@
+use std::sync::Arc;
+
 use super::super::render::hit::{Hitable, Info as HitInfo};
+use super::super::render::light::{AreaLight, Light};
 use super::super::render::material::Material;
+use super::aabbox::{AABBox3, ExpandableToOther};
+use super::consts::PI;
+use super::float::gamma;
 use super::ray::Ray3;
-use super::vector::Vec3;
+use super::vector::{Vec2, Vec3};
+use super::Real;
 
 pub struct Sphere {
     pub center: Vec3,
-    pub radius: f64,
+    pub radius: Real,
     pub material: Box<dyn Material>,
 }
 
-impl Hitable for Sphere {
-    fn hit<'a>(&'a self, r: &Ray3, t_min: f64, t_max: f64) -> Option<HitInfo<'a>> {
-        let oc = &r.o - &self.center;
-        let a = r.d.dot(&r.d);
-        let b = oc.dot(&r.d);
-        let c = oc.dot(&oc) - (self.radius * self.radius);
-        let discriminant = b * b - a * c;
-        if discriminant > 0.0 {
-            let temp = (-b - discriminant.sqrt()) / a;
+fn hit_sphere<'a>(
+    center: &Vec3,
+    radius: Real,
+    material: &'a dyn Material,
+    r: &Ray3,
+    t_min: Real,
+    t_max: Real,
+) -> Option<HitInfo<'a>> {
+    let oc = &r.o - center;
+    let a = r.d.dot(&r.d);
+    let b = oc.dot(&r.d);
+    let c = oc.dot(&oc) - (radius * radius);
+    // b^2 - a c rewritten around the point of the ray closest to the center,
+    // which does not cancel for rays that start far away.
+    let closest = &oc - &(&r.d * (b / a));
+    let closest_length = closest.length();
+    let discriminant = a * (radius - closest_length) * (radius + closest_length);
+    if discriminant > 0.0 {
+        // The root that does not cancel comes first and the other from their
+        // product, so the one near a ray leaving the sphere stays accurate.
+        let sqrt_d = discriminant.sqrt();
+        let q = if b > 0.0 { -(b + sqrt_d) } else { sqrt_d - b };
+        let (t0, t1) = (q / a, c / q);
+        for temp in [t0.min(t1), t0.max(t1)].iter() {
+            let temp = *temp;
             if temp < t_max && temp > t_min {
-                let p = r.point_at_parameter(temp);
-                return Some(HitInfo {
-                    t: temp,
-                    p,
-                    n: &(&p - &self.center) / self.radius,
-                    m: self.material.as_ref(),
-                });
-            }
-            let temp = (-b + discriminant.sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                let p = r.point_at_parameter(temp);
-                return Some(HitInfo {
-                    t: temp,
-                    p,
-                    n: &(&p - &self.center) / self.radius,
-                    m: self.material.as_ref(),
-                });
+                // Pulling the point back on the sphere leaves only the
+                // error of this projection.
+                let local = &r.point_at_parameter(temp) - center;
+                let n = &local / local.length();
+                return Some(get_surface_info(center, radius, &n, temp, material));
             }
         }
-        None
+    }
+    None
+}
+
+/// Hit information of the point of the sphere with the unit normal `n`.
+pub fn get_surface_info<'a>(
+    center: &Vec3,
+    radius: Real,
+    n: &Vec3,
+    t: Real,
+    material: &'a dyn Material,
+) -> HitInfo<'a> {
+    let local = n * radius;
+    let p = center + &local;
+    HitInfo {
+        t,
+        p,
+        p_error: &(&local.abs() * gamma(5)) + &(&p.abs() * gamma(1)),
+        uv: get_uv(n),
+        n: *n,
+        ng: *n,
+        m: material,
+    }
+}
+
+fn sphere_bounds(center: &Vec3, radius: Real) -> AABBox3 {
+    let r = Vec3 {
+        x: radius,
+        y: radius,
+        z: radius,
+    };
+    AABBox3 {
+        blf: center - &r,
+        trr: center + &r,
+    }
+}
+
+impl Hitable for Sphere {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        hit_sphere(
+            &self.center,
+            self.radius,
+            self.material.as_ref(),
+            r,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        Some(sphere_bounds(&self.center, self.radius))
+    }
+
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Light>>) {
+        if let Some(emission) = self.material.get_emission() {
+            lights.push(Arc::new(AreaLight::new(Sphere {
+                center: self.center,
+                radius: self.radius,
+                material: Box::new(emission.clone()),
+            })));
+        }
+    }
+}
+
+/// A sphere that moves linearly from `center0` at `time0` to `center1` at
+/// `time1` and stays at those ends outside of the interval.
+pub struct MovingSphere {
+    pub center0: Vec3,
+    pub center1: Vec3,
+    pub time0: Real,
+    pub time1: Real,
+    pub radius: Real,
+    pub material: Box<dyn Material>,
+}
+
+impl MovingSphere {
+    pub fn get_center(&self, time: Real) -> Vec3 {
+        if self.time1 <= self.time0 {
+            return self.center0;
+        }
+        let s = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        &self.center0 + &(&(&self.center1 - &self.center0) * s)
+    }
+}
+
+impl Hitable for MovingSphere {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        hit_sphere(
+            &self.get_center(r.time),
+            self.radius,
+            self.material.as_ref(),
+            r,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        let mut b = sphere_bounds(&self.center0, self.radius);
+        b.expand(&sphere_bounds(&self.center1, self.radius));
+        Some(b)
+    }
+}
+
+/// Longitude and latitude of a point of the unit sphere mapped to [0, 1],
+/// v grows from -y to +y and u goes around y starting from -x.
+pub fn get_uv(n: &Vec3) -> Vec2 {
+    let theta = (-n.y).clamp(-1.0, 1.0).acos();
+    let phi = (-n.z).atan2(n.x) + PI;
+    Vec2 {
+        x: phi / (2.0 * PI),
+        y: theta / PI,
     }
 }
@@ -1,66 +1,546 @@
-// use vector::Vec3;
-// use triangle::Triangles;
-//
-// pub fn linearTessellation<T>(u: &T, v: &T, t: &Triangle<T>, vertices: &[Vertex]) -> Vec3<T> {
-//     let w = 1 - (u + v);
-//     (vertices[t.indices[0]].pos * u) + (vertices[t.indices[1]].pos * v) + (vertices[t.indices[2]].pos * w)
-// }
-//
-// pub fn projectOnPlane<T>(p: &Vec3<T>, pop: &Vec3<T>, nop: &Vec3<T>) -> Vec3<T> {
-//     p + (nop.dot(pop - p) * nop)
-// }
-//
-// pub fn curveTesselation<T>(u: &T, v: &T, t: &Triangle<T>, vertices: &[Vertex]) -> Vec3<T> {
-//     let w = 1 - (u + v);
-//     let p = (vertices[t.indices[0]].pos * u) + (vertices[t.indices[1]].pos * v) + (vertices[t.indices[2]].pos * w);
-//     let c0 = projectOnPlane(p, vertices[t.indices[0]].pos, vertices[t.indices[0]].normal);
-//     let c1 = projectOnPlane(p, vertices[t.indices[1]].pos, vertices[t.indices[1]].normal);
-//     let c2 = projectOnPlane(p, vertices[t.indices[2]].pos, vertices[t.indices[2]].normal);
-//     c0 * u + c1 * v + c2 * w
-// }
-//
-// pub fn curveTesselation<T>(u: &T, v: &T, t: &Triangle<T>, vertices: &[Vertex]) -> Vec3<T> {
-//     let w = 1 - (u + v);
-//     let p = (vertices[t.indices[0]].pos * u) + (vertices[t.indices[1]].pos * v) + (vertices[t.indices[2]].pos * w);
-//     let c0 = projectOnPlane(p, vertices[t.indices[0]].pos, vertices[t.indices[0]].normal);
-//     let c1 = projectOnPlane(p, vertices[t.indices[1]].pos, vertices[t.indices[1]].normal);
-//     let c2 = projectOnPlane(p, vertices[t.indices[2]].pos, vertices[t.indices[2]].normal);
-//     c0 * u + c1 * v + c2 * w
-// }
-//
-// pub fn curveWithAlphaWeightTesselation<T>(alpha: &T, u: &T, v: &T, t: &Triangle<T>, vertices: &[Vertex]) -> Vec3<T> {
-//     let w = 1 - (u + v);
-//     let p = (vertices[t.indices[0]].pos * u) + (vertices[t.indices[1]].pos * v) + (vertices[t.indices[2]].pos * w);
-//     let c0 = projectOnPlane(p, vertices[t.indices[0]].pos, vertices[t.indices[0]].normal);
-//     let c1 = projectOnPlane(p, vertices[t.indices[1]].pos, vertices[t.indices[1]].normal);
-//     let c2 = projectOnPlane(p, vertices[t.indices[2]].pos, vertices[t.indices[2]].normal);
-//     let c = c0 * u + c1 * v + c2 * w
-//     p * (1.0 - alpha) + c * alpha
-// }
-//
-// pub fn does_intersect<T>(t: &Triangle<T>, r: Ray3<T>, v: &[Vertex]) -> bool {
-//     let p0_p2 = v[t.indices[0]].pos - v[t.indices[2]].pos;
-//     let p1_p2 = v[t.indices[1]].pos - v[t.indices[2]].pos;
-//     let p0_p2_n0_n0 = p0_p2.dot(v[t.indices[0]].nrm) * v[t.indices[0]].nrm;
-//     let p0_p2_n1_n1 = p0_p2.dot(v[t.indices[1]].nrm) * v[t.indices[1]].nrm;
-//     let p0_p2_n2_n2 = p0_p2.dot(v[t.indices[2]].nrm) * v[t.indices[2]].nrm;
-//     let p1_p2_n0_n0 = p1_p2.dot(v[t.indices[0]].nrm) * v[t.indices[0]].nrm;
-//     let p1_p2_n1_n1 = p1_p2.dot(v[t.indices[1]].nrm) * v[t.indices[1]].nrm;
-//     let p1_p2_n2_n2 = p1_p2.dot(v[t.indices[2]].nrm) * v[t.indices[2]].nrm;
-//     let d = p0_p2_n2_n2 - p0_p2_n0_n0;
-//     let c = p1_p2_n2_n2 - p0_p2_n1_n1;
-//     let e = p1_p2_n2_n2 + p0_p2_n2_n2 - (p1_p2_n0_n0 + p1_p2_n1_n1);
-//     const POS_EPSILON: T = 0.000001
-//     const NEG_EPSILON: T = -0.000001
-//     if r.d.x < POS_EPSILON && r.d.x > NEG_EPSILON {
-//         if d.x < POS_EPSILON && d.x > NEG_EPSILON {
-//             P
-//         } else {
-//
-//         }
-//     } else if r.d.y < POS_EPSILON && r.d.y > NEG_EPSILON {
-//
-//     } else {
-//
-//     }
-// }
+use super::super::render::hit::{Hitable, Info as HitInfo};
+use super::aabbox::{AABBox3, ExpandableToPoint3};
+use super::float::gamma;
+use super::polynomial::solve_polynomial;
+use super::ray::Ray3;
+use super::triangle::Triangle;
+use super::vector::Vec3;
+use super::Real;
+
+/// Hits are accepted this far outside the patch, so rays can not slip
+/// between two neighbours that share an edge curve.
+const EDGE_EPSILON: Real = 1e-7;
+/// Patches that bulge less than this fraction of their size are intersected
+/// as flat triangles, their conics are too close to straight lines.
+const FLATNESS: Real = 1e-6;
+/// Newton steps that refine the closed form solution.
+const POLISH_STEPS: usize = 3;
+
+/// `p` projected on the plane through `pop` with the unit normal `nop`.
+pub fn project_on_plane(p: &Vec3, pop: &Vec3, nop: &Vec3) -> Vec3 {
+    p - &(nop * (p - pop).dot(nop))
+}
+
+/// Phong tessellation (Boubekeur and Alexa 2008) of a mesh triangle: the
+/// point of the flat triangle is projected on the tangent planes of the
+/// three vertices and the projections are blended back with the barycentric
+/// weights. `alpha` moves the surface from flat (0) to fully curved (1).
+///
+/// The result is a quadratic patch
+/// `S(u, v) = sum(w_i^2 p_i) + sum(w_i w_j k_ij)` with `w = (1 - u - v, u, v)`
+/// which is hit directly, no triangles are generated.
+#[derive(Clone)]
+pub struct PhongTriangle {
+    triangle: Triangle,
+    alpha: Real,
+    /// `k_01`, `k_02`, `k_12`.
+    k: [Vec3; 3],
+    flat: bool,
+}
+
+impl PhongTriangle {
+    pub fn new(triangle: Triangle, alpha: Real) -> Self {
+        let p = triangle.get_positions();
+        let n = triangle.get_normals();
+        let face = triangle.get_face_normal();
+        // Vertices without normals keep the triangle flat around them.
+        let n: Vec<Vec3> = n
+            .iter()
+            .map(|n| {
+                if n.squared_length() > 0.0 {
+                    n.normalized()
+                } else {
+                    face
+                }
+            })
+            .collect();
+        let edge = |i: usize, j: usize| {
+            let linear = &p[i] + &p[j];
+            let curved =
+                &project_on_plane(&p[j], &p[i], &n[i]) + &project_on_plane(&p[i], &p[j], &n[j]);
+            &(&linear * (1.0 - alpha)) + &(&curved * alpha)
+        };
+        let k = [edge(0, 1), edge(0, 2), edge(1, 2)];
+        let size = (&p[1] - &p[0])
+            .length()
+            .max((&p[2] - &p[0]).length())
+            .max((&p[2] - &p[1]).length());
+        let bulge = [(0, 1, 0), (0, 2, 1), (1, 2, 2)]
+            .iter()
+            .map(|&(i, j, e)| (&k[e] - &(&p[i] + &p[j])).length())
+            .fold(0.0, Real::max);
+        PhongTriangle {
+            triangle,
+            alpha,
+            k,
+            flat: bulge <= size * FLATNESS,
+        }
+    }
+
+    pub fn get_triangle(&self) -> &Triangle {
+        &self.triangle
+    }
+
+    pub fn get_alpha(&self) -> Real {
+        self.alpha
+    }
+
+    /// The point of the curved surface at the barycentric coordinates
+    /// (`u`, `v`) of the second and third vertices.
+    pub fn get_point(&self, u: Real, v: Real) -> Vec3 {
+        let p = self.triangle.get_positions();
+        let w = [1.0 - u - v, u, v];
+        let mut s =
+            &(&(&p[0] * (w[0] * w[0])) + &(&p[1] * (w[1] * w[1]))) + &(&p[2] * (w[2] * w[2]));
+        s += &(&self.k[0] * (w[0] * w[1]));
+        s += &(&self.k[1] * (w[0] * w[2]));
+        s += &(&self.k[2] * (w[1] * w[2]));
+        s
+    }
+
+    /// Bound of the rounding error of `get_point` per axis.
+    pub fn get_point_error(&self, u: Real, v: Real) -> Vec3 {
+        let p = self.triangle.get_positions();
+        let w = [1.0 - u - v, u, v];
+        let mut s = &(&(&p[0].abs() * (w[0] * w[0])) + &(&p[1].abs() * (w[1] * w[1])))
+            + &(&p[2].abs() * (w[2] * w[2]));
+        s += &(&self.k[0].abs() * (w[0] * w[1]).abs());
+        s += &(&self.k[1].abs() * (w[0] * w[2]).abs());
+        s += &(&self.k[2].abs() * (w[1] * w[2]).abs());
+        &s * gamma(9)
+    }
+
+    /// Normal of the curved surface itself at (`u`, `v`), the cross product
+    /// of its partial derivatives, the flat one where they degenerate.
+    pub fn get_geometric_normal(&self, u: Real, v: Real) -> Vec3 {
+        let p = self.triangle.get_positions();
+        let w = [1.0 - u - v, u, v];
+        let p0 = &p[0] * (-2.0 * w[0]);
+        let du = &(&(&(&p0 + &(&p[1] * (2.0 * w[1]))) + &(&self.k[0] * (w[0] - w[1])))
+            - &(&self.k[1] * w[2]))
+            + &(&self.k[2] * w[2]);
+        let dv = &(&(&(&p0 + &(&p[2] * (2.0 * w[2]))) - &(&self.k[0] * w[1]))
+            + &(&self.k[1] * (w[0] - w[2])))
+            + &(&self.k[2] * w[1]);
+        let n = du.cross(&dv);
+        if n.squared_length() > 0.0 {
+            return n.normalized();
+        }
+        self.triangle.get_face_normal()
+    }
+
+    /// The patch stays inside the hull of its quadratic Bezier control
+    /// points, the vertices and half of every `k`.
+    pub fn get_aabb(&self) -> AABBox3 {
+        let mut b = self.triangle.get_aabb();
+        for k in self.k.iter() {
+            b.expand(&(k * 0.5));
+        }
+        b
+    }
+
+    /// Coefficients of `n . (S(u, v) - o)` in the order 1, u, v, u^2, uv, v^2.
+    fn plane_coefficients(&self, n: &Vec3, o: &Vec3) -> [Real; 6] {
+        let p = self.triangle.get_positions();
+        // sum(w) = 1 so `o` can be written as sum(w_i^2 o) + sum(2 w_i w_j o).
+        let a = [
+            n.dot(&(&p[0] - o)),
+            n.dot(&(&p[1] - o)),
+            n.dot(&(&p[2] - o)),
+        ];
+        let o2 = o * 2.0;
+        let k = [
+            n.dot(&(&self.k[0] - &o2)),
+            n.dot(&(&self.k[1] - &o2)),
+            n.dot(&(&self.k[2] - &o2)),
+        ];
+        [
+            a[0],
+            k[0] - 2.0 * a[0],
+            k[1] - 2.0 * a[0],
+            a[0] + a[1] - k[0],
+            2.0 * a[0] - k[0] - k[1] + k[2],
+            a[0] + a[2] - k[1],
+        ]
+    }
+
+    /// Returns the closest hit inside (`tmin`, `tmax`) as distance and
+    /// barycentric coordinates.
+    ///
+    /// The ray is the intersection of two planes that contain it, so a hit
+    /// is a common point of the two conics these planes cut from the patch
+    /// in the (u, v) domain. A degenerate member of their pencil is a pair
+    /// of lines through all the common points, so the lines are intersected
+    /// with one of the conics instead (Richter-Gebert, Perspectives on
+    /// Projective Geometry, 2011).
+    pub fn intersect(&self, r: &Ray3, tmin: Real, tmax: Real) -> Option<(Real, Real, Real)> {
+        if self.flat {
+            return self.triangle.intersect(r, tmin, tmax);
+        }
+        let (n1, n2) = r.d.normalized().get_perpendiculars();
+        let f = self.plane_coefficients(&n1, &r.o);
+        let g = self.plane_coefficients(&n2, &r.o);
+        let fm = conic_matrix(&f);
+        let gm = conic_matrix(&g);
+        // det(F + l G) as a cubic in l, a missing root means G is degenerate.
+        let col = |m: &[[Real; 3]; 3], i: usize| [m[0][i], m[1][i], m[2][i]];
+        let (f0, f1, f2) = (col(&fm, 0), col(&fm, 1), col(&fm, 2));
+        let (g0, g1, g2) = (col(&gm, 0), col(&gm, 1), col(&gm, 2));
+        let (ls, ls_count) = solve_polynomial(&[
+            det3(&f0, &f1, &f2),
+            det3(&g0, &f1, &f2) + det3(&f0, &g1, &f2) + det3(&f0, &f1, &g2),
+            det3(&f0, &g1, &g2) + det3(&g0, &f1, &g2) + det3(&g0, &g1, &f2),
+            det3(&g0, &g1, &g2),
+            0.0,
+        ]);
+        let (degenerate, other) = if ls_count > 0 {
+            let l = ls[0];
+            let mut d = fm;
+            for (dr, gr) in d.iter_mut().zip(gm.iter()) {
+                for (d, g) in dr.iter_mut().zip(gr.iter()) {
+                    *d += l * g;
+                }
+            }
+            (d, &g)
+        } else {
+            (gm, &f)
+        };
+
+        let dd = r.d.dot(&r.d);
+        let mut result = None;
+        let mut closest = tmax;
+        for line in split_degenerate(&degenerate).iter() {
+            let (roots, count) = intersect_line(line, other);
+            for &(u, v) in roots.iter().take(count) {
+                let (u, v) = polish(&f, &g, u, v);
+                if u < -EDGE_EPSILON || v < -EDGE_EPSILON || u + v > 1.0 + EDGE_EPSILON {
+                    continue;
+                }
+                let t = r.d.dot(&(&self.get_point(u, v) - &r.o)) / dd;
+                if t > tmin && t < closest {
+                    closest = t;
+                    result = Some((t, u, v));
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Symmetric matrix of the conic with the coefficients of 1, u, v, u^2, uv,
+/// v^2 acting on (u, v, 1).
+fn conic_matrix(c: &[Real; 6]) -> [[Real; 3]; 3] {
+    [
+        [c[3], c[4] * 0.5, c[1] * 0.5],
+        [c[4] * 0.5, c[5], c[2] * 0.5],
+        [c[1] * 0.5, c[2] * 0.5, c[0]],
+    ]
+}
+
+fn det3(a: &[Real; 3], b: &[Real; 3], c: &[Real; 3]) -> Real {
+    a[0] * (b[1] * c[2] - b[2] * c[1]) - b[0] * (a[1] * c[2] - a[2] * c[1])
+        + c[0] * (a[1] * b[2] - a[2] * b[1])
+}
+
+/// The two lines `l0 u + l1 v + l2 = 0` a degenerate conic consists of,
+/// empty when they are complex.
+fn split_degenerate(a: &[[Real; 3]; 3]) -> Vec<[Real; 3]> {
+    // For a = g h^T + h g^T the adjugate is -p p^T with p = g x h, the
+    // point the lines cross at.
+    let adj = |i: usize, j: usize| {
+        let (r0, r1) = ((j + 1) % 3, (j + 2) % 3);
+        let (c0, c1) = ((i + 1) % 3, (i + 2) % 3);
+        a[r0][c0] * a[r1][c1] - a[r0][c1] * a[r1][c0]
+    };
+    let mut b = [[0.0; 3]; 3];
+    for (i, row) in b.iter_mut().enumerate() {
+        for (j, x) in row.iter_mut().enumerate() {
+            *x = -adj(i, j);
+        }
+    }
+    let scale = a.iter().flatten().fold(0.0, |m: Real, x| m.max(x.abs()));
+    let i = (0..3)
+        .max_by(|x, y| b[*x][*x].abs().partial_cmp(&b[*y][*y].abs()).unwrap())
+        .unwrap();
+    if b[i][i].abs() <= scale * scale * Real::EPSILON {
+        // Rank one, a = l l^T is a double line.
+        let i = (0..3)
+            .max_by(|x, y| a[*x][*x].abs().partial_cmp(&a[*y][*y].abs()).unwrap())
+            .unwrap();
+        let s = a[i][i].abs().sqrt();
+        if s == 0.0 {
+            return Vec::new();
+        }
+        return vec![[a[i][0] / s, a[i][1] / s, a[i][2] / s]];
+    }
+    if b[i][i] < 0.0 {
+        return Vec::new();
+    }
+    let beta = b[i][i].sqrt();
+    let p = [b[0][i] / beta, b[1][i] / beta, b[2][i] / beta];
+    // a plus the cross product matrix of p is the rank one g h^T.
+    let c = [
+        [a[0][0], a[0][1] + p[2], a[0][2] - p[1]],
+        [a[1][0] - p[2], a[1][1], a[1][2] + p[0]],
+        [a[2][0] + p[1], a[2][1] - p[0], a[2][2]],
+    ];
+    let mut best = (0, 0);
+    for (i, row) in c.iter().enumerate() {
+        for (j, x) in row.iter().enumerate() {
+            if x.abs() > c[best.0][best.1].abs() {
+                best = (i, j);
+            }
+        }
+    }
+    let (i, j) = best;
+    vec![c[i], [c[0][j], c[1][j], c[2][j]]]
+}
+
+/// Common points of the line `l0 u + l1 v + l2 = 0` and the conic `c`.
+fn intersect_line(l: &[Real; 3], c: &[Real; 6]) -> ([(Real, Real); 2], usize) {
+    let mut points = [(0.0, 0.0); 2];
+    if l[0] == 0.0 && l[1] == 0.0 {
+        // The line at infinity.
+        return (points, 0);
+    }
+    let solve_along =
+        |a: Real, b: Real, c0: Real, c1: Real, c2: Real, c11: Real, c12: Real, c22: Real| {
+            // x free and y = a x + b.
+            solve_polynomial(&[
+                c0 + c2 * b + c22 * b * b,
+                c1 + c2 * a + c12 * b + 2.0 * c22 * a * b,
+                c11 + c12 * a + c22 * a * a,
+                0.0,
+                0.0,
+            ])
+        };
+    if l[1].abs() >= l[0].abs() {
+        let (a, b) = (-l[0] / l[1], -l[2] / l[1]);
+        let (us, n) = solve_along(a, b, c[0], c[1], c[2], c[3], c[4], c[5]);
+        for (p, u) in points.iter_mut().zip(us.iter()).take(n.min(2)) {
+            *p = (*u, a * u + b);
+        }
+        (points, n.min(2))
+    } else {
+        let (a, b) = (-l[1] / l[0], -l[2] / l[0]);
+        let (vs, n) = solve_along(a, b, c[0], c[2], c[1], c[5], c[4], c[3]);
+        for (p, v) in points.iter_mut().zip(vs.iter()).take(n.min(2)) {
+            *p = (a * v + b, *v);
+        }
+        (points, n.min(2))
+    }
+}
+
+/// Newton steps on the two plane equations `f` and `g`.
+fn polish(f: &[Real; 6], g: &[Real; 6], mut u: Real, mut v: Real) -> (Real, Real) {
+    let eval = |c: &[Real; 6], u: Real, v: Real| {
+        (
+            c[0] + c[1] * u + c[2] * v + c[3] * u * u + c[4] * u * v + c[5] * v * v,
+            c[1] + 2.0 * c[3] * u + c[4] * v,
+            c[2] + c[4] * u + 2.0 * c[5] * v,
+        )
+    };
+    for _ in 0..POLISH_STEPS {
+        let (fv, fu, fvv) = eval(f, u, v);
+        let (gv, gu, gvv) = eval(g, u, v);
+        let det = fu * gvv - fvv * gu;
+        if det == 0.0 {
+            break;
+        }
+        u -= (fv * gvv - fvv * gv) / det;
+        v -= (fu * gv - fv * gu) / det;
+    }
+    (u, v)
+}
+
+impl Hitable for PhongTriangle {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        let (t, u, v) = self.intersect(r, t_min, t_max)?;
+        Some(HitInfo {
+            t,
+            p: self.get_point(u, v),
+            p_error: self.get_point_error(u, v),
+            n: self.triangle.get_normal(u, v),
+            ng: self.get_geometric_normal(u, v),
+            uv: self.triangle.get_texture_coord(u, v),
+            m: self.triangle.get_material().as_ref(),
+        })
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        Some(self.get_aabb())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::super::super::render::material::Lambertian;
+    use super::super::super::render::vertex::Vertex;
+    use super::super::triangle::MeshData;
+    use super::*;
+
+    /// The unit right triangle on z = 0 with the vertex normals `normals`.
+    fn triangle(normals: &[Vec3; 3]) -> Triangle {
+        let ps = [
+            Vec3::new(),
+            Vec3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Vec3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+        ];
+        let vertices: Vec<Vertex> = ps
+            .iter()
+            .zip(normals.iter())
+            .map(|(p, n)| {
+                let mut v = Vertex::new();
+                v.ps = *p;
+                v.nr = *n;
+                v
+            })
+            .collect();
+        let data = Arc::new(MeshData {
+            vertices,
+            material: Arc::new(Lambertian::new(Vec3::new())),
+        });
+        Triangle::new(&[0, 1, 2], &data)
+    }
+
+    fn up() -> Vec3 {
+        Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
+        }
+    }
+
+    /// Normals that lean away from the middle, so the patch bulges up.
+    fn dome(alpha: Real) -> PhongTriangle {
+        let flat = triangle(&[up(), up(), up()]);
+        let mid = flat.get_midpoint();
+        let p = flat.get_positions();
+        let n = [0, 1, 2].map(|i| (&(&(&p[i] - &mid) * 0.5) + &up()).normalized());
+        PhongTriangle::new(triangle(&n), alpha)
+    }
+
+    /// Rays coming down on a grid over the triangle, a little slanted.
+    fn rays() -> Vec<Ray3> {
+        let d = Vec3 {
+            x: 0.05,
+            y: -0.03,
+            z: -1.0,
+        };
+        let mut rays = Vec::new();
+        for i in 0..12 {
+            for j in 0..12 {
+                let o = Vec3 {
+                    x: (i as Real + 0.5) / 12.0,
+                    y: (j as Real + 0.5) / 12.0,
+                    z: 2.0,
+                };
+                rays.push(Ray3::new(o, d));
+            }
+        }
+        rays
+    }
+
+    #[test]
+    fn hits_lie_on_the_ray_and_the_patch() {
+        let patch = dome(1.0);
+        assert!(!patch.flat);
+        let mut hits = 0;
+        for r in rays().iter() {
+            if let Some((t, u, v)) = patch.intersect(r, 0.0, Real::MAX) {
+                hits += 1;
+                assert!(u >= -EDGE_EPSILON && v >= -EDGE_EPSILON);
+                assert!(u + v <= 1.0 + EDGE_EPSILON);
+                let gap = (&r.point_at_parameter(t) - &patch.get_point(u, v)).length();
+                assert!(gap < 1e-4, "hit is {} away from the patch", gap);
+                // The bulge is above the flat triangle.
+                assert!(patch.get_point(u, v).z >= 0.0);
+            }
+        }
+        assert!(hits > 50, "only {} rays hit", hits);
+    }
+
+    #[test]
+    fn zero_curvature_hits_like_the_flat_triangle() {
+        let mut patch = PhongTriangle::new(triangle(&[up(), up(), up()]), 1.0);
+        assert!(patch.flat);
+        // Go through the conics anyway.
+        patch.flat = false;
+        let mut hits = 0;
+        for r in rays().iter() {
+            let curved = patch.intersect(r, 0.0, Real::MAX);
+            let flat = patch.get_triangle().intersect(r, 0.0, Real::MAX);
+            match (curved, flat) {
+                (Some(c), Some(f)) => {
+                    hits += 1;
+                    assert!((c.0 - f.0).abs() < 1e-4);
+                    assert!((c.1 - f.1).abs() < 1e-4);
+                    assert!((c.2 - f.2).abs() < 1e-4);
+                }
+                (None, None) => {}
+                _ => panic!("{:?} and {:?} disagree", curved, flat),
+            }
+        }
+        assert!(hits > 50);
+    }
+
+    #[test]
+    fn rays_away_from_the_patch_miss() {
+        let patch = dome(1.0);
+        let down = Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: -1.0,
+        };
+        let beside = Vec3 {
+            x: 3.0,
+            y: 3.0,
+            z: 2.0,
+        };
+        let above = Vec3 {
+            x: 0.25,
+            y: 0.25,
+            z: 2.0,
+        };
+        let along = Vec3 {
+            x: 1.0,
+            y: 1.0,
+            z: 0.0,
+        };
+        let misses = [
+            Ray3::new(beside, down),
+            Ray3::new(above, up()),
+            Ray3::new(
+                Vec3 {
+                    x: -1.0,
+                    y: -1.0,
+                    z: 1.5,
+                },
+                along,
+            ),
+            Ray3::new(
+                Vec3 {
+                    x: -1.0,
+                    y: 0.25,
+                    z: -0.5,
+                },
+                along,
+            ),
+        ];
+        for r in misses.iter() {
+            assert_eq!(patch.intersect(r, 0.0, Real::MAX), None);
+        }
+        // The hit is past the end of the ray.
+        assert_eq!(patch.intersect(&Ray3::new(above, down), 0.0, 1.0), None);
+    }
+}
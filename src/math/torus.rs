@@ -0,0 +1,301 @@
+use super::super::render::hit::{Hitable, Info as HitInfo};
+use super::super::render::material::Material;
+use super::aabbox::AABBox3;
+use super::consts::PI;
+use super::float::gamma;
+use super::polynomial::solve_quartic;
+use super::ray::Ray3;
+use super::vector::{Vec2, Vec3};
+use super::Real;
+
+/// Newton steps at most that refine the roots of the quartic solver.
+const POLISH_STEPS: usize = 8;
+
+/// Torus around `axis`, `major_radius` is the distance from the center to
+/// the middle of the tube. u is the angle around the axis and v the angle
+/// around the tube, both over 2 pi.
+pub struct Torus {
+    center: Vec3,
+    axis: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    major_radius: Real,
+    minor_radius: Real,
+    pub material: Box<dyn Material>,
+}
+
+impl Torus {
+    pub fn new(
+        center: Vec3,
+        axis: Vec3,
+        major_radius: Real,
+        minor_radius: Real,
+        material: Box<dyn Material>,
+    ) -> Self {
+        let axis = axis.normalized();
+        let (tangent, bitangent) = axis.get_perpendiculars();
+        Torus {
+            center,
+            axis,
+            tangent,
+            bitangent,
+            major_radius,
+            minor_radius,
+            material,
+        }
+    }
+
+    /// Coordinates in the torus space, where the axis is y.
+    fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3 {
+            x: v.dot(&self.tangent),
+            y: v.dot(&self.axis),
+            z: v.dot(&self.bitangent),
+        }
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        &(&(&self.tangent * v.x) + &(&self.axis * v.y)) + &(&self.bitangent * v.z)
+    }
+}
+
+impl Hitable for Torus {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        let d_len = r.d.length();
+        let d = self.to_local(&(&r.d / d_len));
+        let o = self.to_local(&(&r.o - &self.center));
+        // Starting from the point closest to the center keeps the quartic's
+        // coefficients small, far away rays would lose all precision otherwise.
+        let shift = -o.dot(&d);
+        let o = &o + &(&d * shift);
+        let bound = self.major_radius + self.minor_radius;
+        if o.squared_length() > bound * bound {
+            return None;
+        }
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (p.x^2 + p.z^2), with p = o + s d,
+        // solved on the torus scaled to unit size so the solver's thresholds
+        // do not depend on the scene's scale.
+        let o = &o / bound;
+        let major = self.major_radius / bound;
+        let minor = self.minor_radius / bound;
+        let rr4 = 4.0 * major * major;
+        let n = o.dot(&d);
+        let oo = o.squared_length();
+        let k = oo + major * major - minor * minor;
+        let xz = o.x * o.x + o.z * o.z;
+        let xz_d = o.x * d.x + o.z * d.z;
+        let dxz = d.x * d.x + d.z * d.z;
+        let c = [
+            k * k - rr4 * xz,
+            4.0 * n * k - 2.0 * rr4 * xz_d,
+            4.0 * n * n + 2.0 * k - rr4 * dxz,
+            4.0 * n,
+            1.0,
+        ];
+        // Magnitudes of the terms each coefficient sums, they bound the
+        // rounding error of evaluating the quartic.
+        let k_abs = oo + major * major + minor * minor;
+        let c_abs = [
+            k_abs * k_abs + rr4 * xz,
+            4.0 * n.abs() * k_abs + 2.0 * rr4 * xz_d.abs(),
+            4.0 * n * n + 2.0 * k_abs + rr4 * dxz,
+            4.0 * n.abs(),
+            1.0,
+        ];
+        let (roots, count) = solve_quartic(&c);
+        // The value and the first two derivatives of the quartic.
+        let eval = |s: Real| {
+            (
+                (((s + c[3]) * s + c[2]) * s + c[1]) * s + c[0],
+                ((4.0 * s + 3.0 * c[3]) * s + 2.0 * c[2]) * s + c[1],
+                (12.0 * s + 6.0 * c[3]) * s + 2.0 * c[2],
+            )
+        };
+        // Newton steps for as long as they bring the quartic closer to zero.
+        let polish = |mut s: Real| {
+            let mut e = eval(s);
+            for _ in 0..POLISH_STEPS {
+                if e.1 == 0.0 {
+                    break;
+                }
+                let next = s - e.0 / e.1;
+                let next_e = eval(next);
+                if next_e.0.abs() >= e.0.abs() {
+                    break;
+                }
+                s = next;
+                e = next_e;
+            }
+            (s, e)
+        };
+        let mut t = t_max;
+        let mut local_p = None;
+        for s in roots.iter().take(count) {
+            // The closed form roots lose many digits where two of them are
+            // close, as for rays that pass near the tube's silhouette.
+            let (mut s, (mut f, mut df, mut ddf)) = polish(*s);
+            let sa = s.abs();
+            let f_error =
+                gamma(16) * ((((sa + c_abs[3]) * sa + c_abs[2]) * sa + c_abs[1]) * sa + c_abs[0]);
+            // Merging the entry and the exit of a grazing ray leaves a root
+            // between them where the slope vanishes, inside the tube. The
+            // parabola through it leads back to the entry.
+            if f < -f_error && ddf > 0.0 {
+                let (entry, e) = polish(s - (df + (df * df - 2.0 * f * ddf).sqrt()) / ddf);
+                if e.0.abs() < f.abs() {
+                    s = entry;
+                    f = e.0;
+                    df = e.1;
+                    ddf = e.2;
+                }
+            }
+            // The quartic is negative inside the tube. Merging a close pair of
+            // complex roots makes the solver report a real root outside of it,
+            // for a ray that passes the tube by.
+            if f > f_error {
+                continue;
+            }
+            // The root is known up to a Newton step, or where the slope
+            // vanishes at a grazing hit up to the parabola's. Roots closer to
+            // the origin than that are the surface the ray starts on.
+            let f_bound = f.abs() + f_error;
+            let s_error = (f_bound / df.abs()).min((2.0 * f_bound / ddf.abs()).sqrt()) * bound;
+            let tt = (s * bound + shift) / d_len;
+            if tt - s_error / d_len > t_min && tt < t {
+                t = tt;
+                local_p = Some(&(&o + &(&d * s)) * bound);
+            }
+        }
+        let lp = local_p?;
+        let ring = Vec2 { x: lp.x, y: lp.z };
+        let ring_len = ring.length();
+        let ring_center = Vec3 {
+            x: lp.x * self.major_radius / ring_len,
+            y: 0.0,
+            z: lp.z * self.major_radius / ring_len,
+        };
+        let n = (&lp - &ring_center).normalized();
+        let phi = lp.z.atan2(lp.x);
+        let theta = lp.y.atan2(ring_len - self.major_radius);
+        // Pulled back on the tube, then only the change of frame rounds it.
+        let on_tube = self.to_world(&(&ring_center + &(&n * self.minor_radius)));
+        let p = &self.center + &on_tube;
+        let spread = gamma(10) * (self.major_radius + self.minor_radius);
+        let p_error = &Vec3 {
+            x: spread,
+            y: spread,
+            z: spread,
+        } + &(&(&self.center.abs() + &on_tube.abs()) * gamma(1));
+        let n = self.to_world(&n);
+        Some(HitInfo {
+            t,
+            p,
+            p_error,
+            n,
+            ng: n,
+            uv: Vec2 {
+                x: (phi + PI) / (2.0 * PI),
+                y: (theta + PI) / (2.0 * PI),
+            },
+            m: self.material.as_ref(),
+        })
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        let mut b = AABBox3::around_circle(&self.center, &self.axis, self.major_radius);
+        let e = Vec3 {
+            x: self.minor_radius,
+            y: self.minor_radius,
+            z: self.minor_radius,
+        };
+        b.blf -= &e;
+        b.trr += &e;
+        Some(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::render::material::Lambertian;
+    use super::*;
+
+    fn v(x: Real, y: Real, z: Real) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    /// A tilted torus of major radius 1 and minor radius 0.25 away from the
+    /// origin.
+    fn torus() -> Torus {
+        Torus::new(
+            v(0.5, -1.0, 2.0),
+            v(0.3, 1.0, -0.2),
+            1.0,
+            0.25,
+            Box::new(Lambertian::new(Vec3::new())),
+        )
+    }
+
+    /// Distance of the world point `p` from the surface of the tube.
+    fn distance(torus: &Torus, p: &Vec3) -> Real {
+        let lp = torus.to_local(&(p - &torus.center));
+        let ring = (lp.x * lp.x + lp.z * lp.z).sqrt() - torus.major_radius;
+        ((ring * ring + lp.y * lp.y).sqrt() - torus.minor_radius).abs()
+    }
+
+    #[test]
+    fn hits_lie_on_the_tube_and_face_the_ray() {
+        let torus = torus();
+        let mut hits = 0;
+        for i in 0..8 {
+            for j in 0..8 {
+                let a = i as Real * 0.8;
+                let target =
+                    &torus.center + &torus.to_world(&v(a.cos(), j as Real * 0.07 - 0.25, a.sin()));
+                let o = &torus.center + &v(3.0 * (j as Real).cos(), 2.0, 3.0 * (j as Real).sin());
+                let r = Ray3::new(o, &target - &o);
+                if let Some(rec) = torus.hit(&r, 0.0, Real::MAX) {
+                    hits += 1;
+                    assert!((&r.point_at_parameter(rec.t) - &rec.p).length() < 1e-4);
+                    assert!(distance(&torus, &rec.p) < 1e-4);
+                    assert!((rec.n.length() - 1.0).abs() < 1e-4);
+                    assert!(rec.n.dot(&r.d) < 0.0);
+                }
+            }
+        }
+        assert!(hits > 32, "only {} rays hit", hits);
+    }
+
+    #[test]
+    fn rays_through_the_hole_or_beside_the_tube_miss() {
+        let torus = torus();
+        let through = Ray3::new(&torus.center - &(&torus.axis * 3.0), torus.axis);
+        assert!(torus.hit(&through, 0.0, Real::MAX).is_none());
+        let above = &torus.center + &torus.to_world(&v(0.0, 0.26, 1.0));
+        let along = torus.to_world(&v(1.0, 0.0, 0.0));
+        let beside = Ray3::new(&above - &(&along * 3.0), along);
+        assert!(torus.hit(&beside, 0.0, Real::MAX).is_none());
+    }
+
+    #[test]
+    fn grazing_rays_hit_the_top_of_the_tube() {
+        let torus = torus();
+        for k in 2..6 {
+            let eps = (10.0 as Real).powi(-k);
+            for j in 0..12 {
+                let a = j as Real * 0.5;
+                // Tangent to the ring on top of the tube at the angle a.
+                let d = torus.to_world(&v(a.cos(), 0.0, a.sin()));
+                let touch = |y| &torus.center + &torus.to_world(&v(-a.sin(), y, a.cos()));
+                let inside = touch(0.25 - eps);
+                let r = Ray3::new(&inside - &(&d * 3.0), d);
+                let rec = torus.hit(&r, 0.0, Real::MAX);
+                assert!(rec.is_some(), "ray {} below the top missed", eps);
+                assert!(distance(&torus, &rec.unwrap().p) < 1e-4);
+                let outside = touch(0.25 + eps);
+                let r = Ray3::new(&outside - &(&d * 3.0), d);
+                assert!(torus.hit(&r, 0.0, Real::MAX).is_none());
+            }
+        }
+    }
+}
@@ -1,96 +1,244 @@
-use super::vector::{Vec2, Vec3};
-// use ::math::aabbox::AABBox3;
+use std::sync::Arc;
+
+use super::super::render::hit::{Hitable, Info as HitInfo};
+use super::super::render::light::{AreaLight, Light};
+use super::super::render::material::Material;
 use super::super::render::vertex::Vertex;
+use super::aabbox::{AABBox3, ExpandableToPoint3};
+use super::float::gamma;
 use super::ray::Ray3;
+use super::vector::{Vec2, Vec3};
+use super::Real;
 
-#[derive(Debug, Clone, Copy)]
+/// The vertices and the material every triangle of a mesh shares.
+#[derive(Clone)]
+pub struct MeshData {
+    pub vertices: Vec<Vertex>,
+    pub material: Arc<dyn Material>,
+}
+
+/// A triangle of a mesh, it only keeps the indices of its vertices in the
+/// data of the mesh, so it can be hit on its own or as a part of the mesh.
+#[derive(Clone)]
 pub struct Triangle {
-    edg: [Vec3; 2],
-    ind: [usize; 3],
-    tedg: [Vec2; 2],
+    data: Arc<MeshData>,
+    inds: [u32; 3],
 }
 
 impl Triangle {
-    pub fn new(inds: &[usize; 3], vertices: &Vec<Vertex>) -> Triangle {
+    pub fn new(inds: &[usize; 3], data: &Arc<MeshData>) -> Triangle {
+        for i in inds {
+            assert!(*i < data.vertices.len(), "Vertex index is out of range.");
+        }
         Triangle {
-            edg: [
-                &vertices[inds[1]].ps - &vertices[inds[0]].ps,
-                &vertices[inds[2]].ps - &vertices[inds[0]].ps,
-            ],
-            ind: [inds[0], inds[1], inds[2]],
-            tedg: [
-                vertices[inds[1]].uv - vertices[inds[0]].uv,
-                vertices[inds[2]].uv - vertices[inds[0]].uv,
-            ],
+            data: data.clone(),
+            inds: inds.map(|i| i as u32),
+        }
+    }
+
+    fn get_vertex(&self, i: usize) -> &Vertex {
+        &self.data.vertices[self.inds[i] as usize]
+    }
+
+    pub fn get_indices(&self) -> [usize; 3] {
+        self.inds.map(|i| i as usize)
+    }
+
+    pub fn get_material(&self) -> &Arc<dyn Material> {
+        &self.data.material
+    }
+
+    pub fn get_positions(&self) -> [Vec3; 3] {
+        [0, 1, 2].map(|i| self.get_vertex(i).ps)
+    }
+
+    pub fn get_normals(&self) -> [Vec3; 3] {
+        [0, 1, 2].map(|i| self.get_vertex(i).nr)
+    }
+
+    /// Interpolates the texture coordinates at (`u`, `v`).
+    pub fn get_texture_coord(&self, u: Real, v: Real) -> Vec2 {
+        let uv = [0, 1, 2].map(|i| self.get_vertex(i).uv);
+        &(&(&uv[0] * (1.0 - u - v)) + &(&uv[1] * u)) + &(&uv[2] * v)
+    }
+
+    pub fn get_aabb(&self) -> AABBox3 {
+        let mut aabb = AABBox3::new();
+        for p in self.get_positions().iter() {
+            aabb.expand(p);
         }
+        aabb
     }
 
-    pub fn get_texture_coord(&self, u: f64, v: f64) -> Vec2 {
-        self.tedg[0] * u + self.tedg[1] * v
-    }
-
-    // pub fn get_aabb(&self, vertices: &Vec<Vertex>) -> AABBox3 {
-    //     AABBox3 {
-    //         blf: Vec3 {
-    //             x: vertices[self.ind[0]].ps.x.min(vertices[self.ind[1]].ps.x.min(vertices[self.ind[2]].ps.x)),
-    //             y: vertices[self.ind[0]].ps.y.min(vertices[self.ind[1]].ps.y.min(vertices[self.ind[2]].ps.y)),
-    //             z: vertices[self.ind[0]].ps.z.min(vertices[self.ind[1]].ps.z.min(vertices[self.ind[2]].ps.z)),
-    //         },
-    //         trr: Vec3 {
-    //             x: vertices[self.ind[0]].ps.x.max(vertices[self.ind[1]].ps.x.max(vertices[self.ind[2]].ps.x)),
-    //             y: vertices[self.ind[0]].ps.y.max(vertices[self.ind[1]].ps.y.max(vertices[self.ind[2]].ps.y)),
-    //             z: vertices[self.ind[0]].ps.z.max(vertices[self.ind[1]].ps.z.max(vertices[self.ind[2]].ps.z)),
-    //         },
-    //     }
-    // }
-
-    pub fn get_midpoint(&self, vertices: &Vec<Vertex>) -> Vec3 {
-        &(&(&vertices[self.ind[0]].ps + &vertices[self.ind[1]].ps) + &vertices[self.ind[2]].ps)
-            / 3f64
-    }
-
-    pub fn intersect(
-        &self,
-        r: &Ray3,
-        tmin: f64,
-        vertices: &Vec<Vertex>,
-    ) -> Option<(f64, f64, f64)> {
-        let pvec = r.d.cross(&self.edg[1]);
-        let det = self.edg[0].dot(&pvec);
-        if det < 0.0001 && det > -0.0001 {
+    pub fn get_midpoint(&self) -> Vec3 {
+        let ps = self.get_positions();
+        &(&(&ps[0] + &ps[1]) + &ps[2]) / 3.0
+    }
+
+    /// Watertight intersection (Woop et al. 2013), returns the distance and
+    /// the barycentric weights of the second and third vertices.
+    ///
+    /// The vertices are moved to a space where the ray starts at the origin
+    /// and goes along +z, then the hit is decided by the signs of the 2D
+    /// edge functions. Edges shared by two triangles are evaluated with the
+    /// same operations on both sides, so a ray can not slip between them.
+    pub fn intersect(&self, r: &Ray3, tmin: Real, tmax: Real) -> Option<(Real, Real, Real)> {
+        // The axis the ray goes the most along becomes z.
+        let ad = [r.d.x.abs(), r.d.y.abs(), r.d.z.abs()];
+        let kz = if ad[0] > ad[1] {
+            if ad[0] > ad[2] {
+                0
+            } else {
+                2
+            }
+        } else if ad[1] > ad[2] {
+            1
+        } else {
+            2
+        };
+        let kx = (kz + 1) % 3;
+        let ky = (kx + 1) % 3;
+        let d = [r.d.x, r.d.y, r.d.z];
+        let sx = -d[kx] / d[kz];
+        let sy = -d[ky] / d[kz];
+        let sz = 1.0 / d[kz];
+
+        let mut pt = [[0.0; 3]; 3];
+        for (pt, p) in pt.iter_mut().zip(self.get_positions().iter()) {
+            let p = p - &r.o;
+            let p = [p.x, p.y, p.z];
+            *pt = [p[kx] + sx * p[kz], p[ky] + sy * p[kz], p[kz] * sz];
+        }
+
+        let [e0, e1, e2] = edge_functions(&pt);
+        if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+            return None;
+        }
+        let det = e0 + e1 + e2;
+        if det == 0.0 {
             return None;
         }
-        let inv_det = 1f64 / det;
-        let tvec = &r.o - &vertices[self.ind[0]].ps;
-        let u = tvec.dot(&pvec) * inv_det;
-        if u < 0.0001f64 || u > 0.9999f64 {
+
+        // Range test before the division, `t` = `t_scaled` / `det`.
+        let t_scaled = e0 * pt[0][2] + e1 * pt[1][2] + e2 * pt[2][2];
+        if det < 0.0 && (t_scaled >= tmin * det || t_scaled <= tmax * det) {
             return None;
         }
-        let qvec = tvec.cross(&self.edg[0]);
-        let v = r.d.dot(&qvec) * inv_det;
-        if v < 0.0001f64 || u + v > 0.9999f64 {
+        if det > 0.0 && (t_scaled <= tmin * det || t_scaled >= tmax * det) {
             return None;
         }
-        let t = self.edg[1].dot(&qvec) * inv_det; // Set distance along ray to intersection
-        if t < tmin {
-            if t > 0.0001 {
-                return Some((t, u, v));
-            }
+        let inv_det = 1.0 / det;
+        Some((t_scaled * inv_det, e1 * inv_det, e2 * inv_det))
+    }
+
+    /// The point at (`u`, `v`) and the bound of its rounding error, being
+    /// interpolated from the vertices it lies on the triangle whatever error
+    /// the ray distance has.
+    pub fn get_point(&self, u: Real, v: Real) -> (Vec3, Vec3) {
+        let ps = self.get_positions();
+        let terms = [&ps[0] * (1.0 - u - v), &ps[1] * u, &ps[2] * v];
+        let p = &(&terms[0] + &terms[1]) + &terms[2];
+        let p_error = &(&(&terms[0].abs() + &terms[1].abs()) + &terms[2].abs()) * gamma(7);
+        (p, p_error)
+    }
+
+    /// Hit information of the point at (`u`, `v`) found at distance `t`.
+    pub fn get_hit_info(&self, t: Real, u: Real, v: Real) -> HitInfo<'_> {
+        let (p, p_error) = self.get_point(u, v);
+        HitInfo {
+            t,
+            p,
+            p_error,
+            n: self.get_normal(u, v),
+            ng: self.get_face_normal(),
+            uv: self.get_texture_coord(u, v),
+            m: self.data.material.as_ref(),
+        }
+    }
+
+    pub fn get_area(&self) -> Real {
+        let ps = self.get_positions();
+        0.5 * (&ps[1] - &ps[0]).cross(&(&ps[2] - &ps[0])).length()
+    }
+
+    /// Hit information of a uniform point of the triangle for `u` uniform
+    /// in the unit square.
+    pub fn sample_point(&self, u: &Vec2) -> HitInfo<'_> {
+        let su = u.x.sqrt();
+        self.get_hit_info(0.0, su * (1.0 - u.y), su * u.y)
+    }
+
+    pub fn get_face_normal(&self) -> Vec3 {
+        let ps = self.get_positions();
+        (&ps[1] - &ps[0]).cross(&(&ps[2] - &ps[0])).normalized()
+    }
+
+    /// Interpolates the vertex normals at (`u`, `v`), meshes without normals
+    /// get the face normal.
+    pub fn get_normal(&self, u: Real, v: Real) -> Vec3 {
+        let nr = self.get_normals();
+        let n = &(&(&nr[0] * (1.0 - u - v)) + &(&nr[1] * u)) + &(&nr[2] * v);
+        if n.squared_length() > 0.0 {
+            return n.normalized();
         }
-        None
+        self.get_face_normal()
     }
 
-    pub fn barycentric(&self, p: &Vec3, vertices: &Vec<Vertex>) -> Vec3 {
-        let v2_ = p - &vertices[self.ind[0]].ps;
-        let d00 = self.edg[0].dot(&self.edg[0]);
-        let d01 = self.edg[0].dot(&self.edg[1]);
-        let d11 = self.edg[1].dot(&self.edg[1]);
-        let d20 = v2_.dot(&self.edg[0]);
-        let d21 = v2_.dot(&self.edg[1]);
+    pub fn barycentric(&self, p: &Vec3) -> Vec3 {
+        let ps = self.get_positions();
+        let e0 = &ps[1] - &ps[0];
+        let e1 = &ps[2] - &ps[0];
+        let v2_ = p - &ps[0];
+        let d00 = e0.dot(&e0);
+        let d01 = e0.dot(&e1);
+        let d11 = e1.dot(&e1);
+        let d20 = v2_.dot(&e0);
+        let d21 = v2_.dot(&e1);
         let d = d00 * d11 - d01 * d01;
         let v = (d11 * d20 - d01 * d21) / d;
         let w = (d00 * d21 - d01 * d20) / d;
-        let u = 1f64 - v - w;
-        return Vec3 { x: u, y: v, z: w };
+        let u = 1.0 - v - w;
+        Vec3 { x: u, y: v, z: w }
+    }
+}
+
+impl Hitable for Triangle {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        let (t, u, v) = self.intersect(r, t_min, t_max)?;
+        Some(self.get_hit_info(t, u, v))
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        Some(self.get_aabb())
+    }
+
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Light>>) {
+        if self.data.material.get_emission().is_some() {
+            lights.push(Arc::new(AreaLight::new(self.clone())));
+        }
+    }
+}
+
+/// 2D edge functions of the ray space vertices, each one is the weight of
+/// the vertex opposite to its edge.
+fn edge_functions(pt: &[[Real; 3]; 3]) -> [Real; 3] {
+    let e = [
+        pt[1][0] * pt[2][1] - pt[1][1] * pt[2][0],
+        pt[2][0] * pt[0][1] - pt[2][1] * pt[0][0],
+        pt[0][0] * pt[1][1] - pt[0][1] * pt[1][0],
+    ];
+    #[cfg(feature = "single-precision")]
+    {
+        if e.contains(&0.0) {
+            // The ray passes through an edge or a vertex, so the sign is
+            // decided again in double precision.
+            let p = |i: usize, j: usize| f64::from(pt[i][j]);
+            return [
+                (p(1, 0) * p(2, 1) - p(1, 1) * p(2, 0)) as Real,
+                (p(2, 0) * p(0, 1) - p(2, 1) * p(0, 0)) as Real,
+                (p(0, 0) * p(1, 1) - p(0, 1) * p(1, 0)) as Real,
+            ];
+        }
     }
+    e
 }
@@ -1,11 +1,25 @@
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
-use rand::{thread_rng, Rng};
+use super::super::io::file::Stream;
+use super::Real;
+use std::ops::{
+    Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct Vec3 {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+    pub x: Real,
+    pub y: Real,
+    pub z: Real,
 }
 
 macro_rules! as_expr {
@@ -27,9 +41,9 @@ macro_rules! op3 {
             }
         }
 
-        impl<'a> $tra<f64> for &'a Vec3 {
+        impl<'a> $tra<Real> for &'a Vec3 {
             type Output = Vec3;
-            fn $func(self, f: f64) -> Vec3 {
+            fn $func(self, f: Real) -> Vec3 {
                 Vec3 {
                     x: as_expr!(self.x $opt f),
                     y: as_expr!(self.y $opt f),
@@ -55,8 +69,8 @@ macro_rules! opasg3 {
             }
         }
 
-        impl $tra<f64> for Vec3 {
-            fn $func(&mut self, f: f64) {
+        impl $tra<Real> for Vec3 {
+            fn $func(&mut self, f: Real) {
                 as_expr!(self.x $opt f);
                 as_expr!(self.y $opt f);
                 as_expr!(self.z $opt f);
@@ -70,7 +84,7 @@ opasg3!(sub_assign, SubAssign, -=);
 opasg3!(mul_assign, MulAssign, *=);
 opasg3!(div_assign, DivAssign, /=);
 
-impl<'a> Neg for &'a Vec3 {
+impl Neg for &Vec3 {
     type Output = Vec3;
     fn neg(self) -> Vec3 {
         Vec3 {
@@ -81,16 +95,44 @@ impl<'a> Neg for &'a Vec3 {
     }
 }
 
+impl Index<Axis> for Vec3 {
+    type Output = Real;
+    fn index(&self, a: Axis) -> &Real {
+        match a {
+            Axis::X => &self.x,
+            Axis::Y => &self.y,
+            Axis::Z => &self.z,
+        }
+    }
+}
+
+impl IndexMut<Axis> for Vec3 {
+    fn index_mut(&mut self, a: Axis) -> &mut Real {
+        match a {
+            Axis::X => &mut self.x,
+            Axis::Y => &mut self.y,
+            Axis::Z => &mut self.z,
+        }
+    }
+}
+
 impl Vec3 {
     pub fn new() -> Self {
         Self {
-            x: 0f64,
-            y: 0f64,
-            z: 0f64,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
         }
     }
 
-    pub fn dot(&self, o: &Self) -> f64 {
+    /// Reads three single precision floats.
+    pub fn read(&mut self, s: &mut Stream) {
+        self.x = s.read(&0f32) as Real;
+        self.y = s.read(&0f32) as Real;
+        self.z = s.read(&0f32) as Real;
+    }
+
+    pub fn dot(&self, o: &Self) -> Real {
         (self.x * o.x) + (self.y * o.y) + (self.z * o.z)
     }
 
@@ -102,15 +144,29 @@ impl Vec3 {
         }
     }
 
-    pub fn length(&self) -> f64 {
+    pub fn length(&self) -> Real {
         self.squared_length().sqrt()
     }
 
-    pub fn absolute_length(&self) -> f64 {
+    pub fn absolute_length(&self) -> Real {
         self.x.abs() + self.y.abs() + self.z.abs()
     }
 
-    pub fn squared_length(&self) -> f64 {
+    /// Per component absolute value.
+    pub fn abs(&self) -> Self {
+        Self {
+            x: self.x.abs(),
+            y: self.y.abs(),
+            z: self.z.abs(),
+        }
+    }
+
+    /// True for black colours, nothing is worth tracing for them.
+    pub fn is_zero(&self) -> bool {
+        self.x == 0.0 && self.y == 0.0 && self.z == 0.0
+    }
+
+    pub fn squared_length(&self) -> Real {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
@@ -130,7 +186,7 @@ impl Vec3 {
         }
     }
 
-    pub fn refract(&self, n: &Vec3, ni_over_nt: f64) -> Option<Self> {
+    pub fn refract(&self, n: &Vec3, ni_over_nt: Real) -> Option<Self> {
         let uv = self.normalized();
         let dt = uv.dot(n);
         let discriminant = 1.0 - ni_over_nt * ni_over_nt * (1.0 - dt * dt);
@@ -141,28 +197,40 @@ impl Vec3 {
     }
 
     pub fn reflect(&self, n: &Vec3) -> Self {
-        return self - &(n * (2.0 * self.dot(n)));
+        self - &(n * (2.0 * self.dot(n)))
     }
 
-    pub fn random_in_unit_sphere() -> Self {
-        let mut rng = thread_rng();
-        loop {
-            let p = Self {
-                x: rng.gen_range(-1.0f64, 1.0f64),
-                y: rng.gen_range(-1.0f64, 1.0f64),
-                z: rng.gen_range(-1.0f64, 1.0f64),
-            };
-            if p.squared_length() < 1.0 {
-                return p;
-            }
-        }
+    /// Two unit vectors that form a right handed orthonormal basis with
+    /// this unit vector, (Duff et al. 2017) without any branch on a threshold.
+    pub fn get_perpendiculars(&self) -> (Self, Self) {
+        let sign = if self.z < 0.0 { -1.0 } else { 1.0 };
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+        (
+            Self {
+                x: 1.0 + sign * self.x * self.x * a,
+                y: sign * b,
+                z: -sign * self.x,
+            },
+            Self {
+                x: b,
+                y: sign + self.y * self.y * a,
+                z: -self.y,
+            },
+        )
+    }
+}
+
+impl Default for Vec3 {
+    fn default() -> Self {
+        Vec3::new()
     }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct Vec2 {
-    pub x: f64,
-    pub y: f64,
+    pub x: Real,
+    pub y: Real,
 }
 
 macro_rules! op2 {
@@ -177,9 +245,9 @@ macro_rules! op2 {
             }
         }
 
-        impl<'a> $tra<f64> for &'a Vec2 {
+        impl<'a> $tra<Real> for &'a Vec2 {
             type Output = Vec2;
-            fn $func(self, other: f64) -> Vec2 {
+            fn $func(self, other: Real) -> Vec2 {
                 Vec2 {
                     x: as_expr!(self.x $opt other),
                     y: as_expr!(self.y $opt other),
@@ -198,8 +266,8 @@ macro_rules! opasg2 {
             }
         }
 
-        impl $tra<f64> for Vec2 {
-            fn $func(&mut self, other: f64) {
+        impl $tra<Real> for Vec2 {
+            fn $func(&mut self, other: Real) {
                 as_expr!(self.x $opt other);
                 as_expr!(self.y $opt other);
             }
@@ -217,7 +285,7 @@ opasg2!(sub_assign, SubAssign, -=);
 opasg2!(mul_assign, MulAssign, *=);
 opasg2!(div_assign, DivAssign, /=);
 
-impl<'a> Neg for &'a Vec2 {
+impl Neg for &Vec2 {
     type Output = Vec2;
     fn neg(self) -> Vec2 {
         Vec2 {
@@ -229,22 +297,28 @@ impl<'a> Neg for &'a Vec2 {
 
 impl Vec2 {
     pub fn new() -> Self {
-        Self { x: 0f64, y: 0f64 }
+        Self { x: 0.0, y: 0.0 }
+    }
+
+    /// Reads two single precision floats.
+    pub fn read(&mut self, s: &mut Stream) {
+        self.x = s.read(&0f32) as Real;
+        self.y = s.read(&0f32) as Real;
     }
 
-    pub fn dot(&self, o: &Self) -> f64 {
+    pub fn dot(&self, o: &Self) -> Real {
         self.x * o.x + self.y * o.y
     }
 
-    pub fn length(&self) -> f64 {
+    pub fn length(&self) -> Real {
         (self.x * self.x + self.y * self.y).sqrt()
     }
 
-    pub fn absolute_length(&self) -> f64 {
+    pub fn absolute_length(&self) -> Real {
         self.x.abs() + self.y.abs()
     }
 
-    pub fn square_length(&self) -> f64 {
+    pub fn square_length(&self) -> Real {
         self.x * self.x + self.y * self.y
     }
 
@@ -262,3 +336,9 @@ impl Vec2 {
         }
     }
 }
+
+impl Default for Vec2 {
+    fn default() -> Self {
+        Vec2::new()
+    }
+}
@@ -1,24 +1,65 @@
+use super::super::math::quaternion::Quaternion;
 use super::super::math::ray::Ray3;
 use super::super::math::vector::Vec3;
 
+use super::super::math::Real;
+use super::sampler::Sampler;
 use std::fmt::Debug;
 
+const LOCAL_X: Vec3 = Vec3 {
+    x: 1.0,
+    y: 0.0,
+    z: 0.0,
+};
+const LOCAL_Y: Vec3 = Vec3 {
+    x: 0.0,
+    y: 1.0,
+    z: 0.0,
+};
+const LOCAL_Z: Vec3 = Vec3 {
+    x: 0.0,
+    y: 0.0,
+    z: 1.0,
+};
+
 pub trait Camera: Sync + Send + Debug {
-    fn rotate_localy(&mut self, d: f64, v: &Vec3);
-    fn get_ray(&self, x: f64, y: f64) -> Ray3;
+    /// Rotates by `d` radians around `v`, given in the camera's own axes.
+    fn rotate_localy(&mut self, d: Real, v: &Vec3);
+    /// Rotates around `axis` (world space) passing through `target`, moving
+    /// the camera along a circle while it keeps its relative orientation.
+    fn orbit(&mut self, target: &Vec3, d: Real, axis: &Vec3);
+    /// The moment inside the shutter interval is drawn from `sampler`.
+    fn get_ray(&self, x: Real, y: Real, sampler: &mut dyn Sampler) -> Ray3;
+
+    /// Turns around the camera's up axis.
+    fn yaw(&mut self, d: Real) {
+        self.rotate_localy(d, &LOCAL_Y);
+    }
+
+    /// Tilts around the camera's right axis.
+    fn pitch(&mut self, d: Real) {
+        self.rotate_localy(d, &LOCAL_X);
+    }
+
+    /// Rolls around the viewing direction.
+    fn roll(&mut self, d: Real) {
+        self.rotate_localy(d, &LOCAL_Z);
+    }
 }
 
 #[derive(Debug)]
 pub struct Base {
-    screen_ratio: f64,
+    screen_ratio: Real,
     location: Vec3,
     screen_x_axis: Vec3,
     screen_y_axis: Vec3,
     screen_z_axis: Vec3,
+    shutter_open: Real,
+    shutter_close: Real,
 }
 
 impl Base {
-    pub fn new(location: &Vec3, target: &Vec3, up: &Vec3, screen_ratio: f64) -> Base {
+    pub fn new(location: &Vec3, target: &Vec3, up: &Vec3, screen_ratio: Real) -> Base {
         let screen_z_axis = (target - location).normalized();
         let screen_x_axis = screen_z_axis.cross(up).normalized();
         let screen_y_axis = screen_x_axis.cross(&screen_z_axis).normalized();
@@ -26,13 +67,47 @@ impl Base {
         //     screen_x_axis, screen_y_axis, screen_z_axis,
         // );
         Base {
-            screen_ratio: screen_ratio,
+            screen_ratio,
             location: *location,
-            screen_x_axis: screen_x_axis,
-            screen_y_axis: screen_y_axis,
-            screen_z_axis: screen_z_axis,
+            screen_x_axis,
+            screen_y_axis,
+            screen_z_axis,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
         }
     }
+
+    /// Rays are spread over [`open`, `close`], moving objects get blurred
+    /// along the path they take inside it.
+    pub fn set_shutter(&mut self, open: Real, close: Real) {
+        self.shutter_open = open;
+        self.shutter_close = close;
+    }
+
+    /// `s` in [0, 1) picks the moment inside the shutter interval.
+    pub fn get_time(&self, s: Real) -> Real {
+        self.shutter_open + s * (self.shutter_close - self.shutter_open)
+    }
+
+    pub fn rotate_localy(&mut self, d: Real, v: &Vec3) {
+        let axis = &(&(&self.screen_x_axis * v.x) + &(&self.screen_y_axis * v.y))
+            + &(&self.screen_z_axis * v.z);
+        self.rotate(&Quaternion::from_axis_angle(d, &axis.normalized()));
+    }
+
+    pub fn orbit(&mut self, target: &Vec3, d: Real, axis: &Vec3) {
+        let q = Quaternion::from_axis_angle(d, &axis.normalized());
+        self.location = target + &q.rotate(&(&self.location - target));
+        self.rotate(&q);
+    }
+
+    fn rotate(&mut self, q: &Quaternion) {
+        // Renormalizing keeps repeated small rotations from drifting.
+        self.screen_z_axis = q.rotate(&self.screen_z_axis).normalized();
+        self.screen_x_axis = q.rotate(&self.screen_x_axis).normalized();
+        self.screen_y_axis = self.screen_x_axis.cross(&self.screen_z_axis).normalized();
+        self.screen_x_axis = self.screen_z_axis.cross(&self.screen_y_axis).normalized();
+    }
 }
 
 #[derive(Debug)]
@@ -42,19 +117,25 @@ pub struct OrthoCamera {
 
 impl OrthoCamera {
     pub fn new(base: Base) -> OrthoCamera {
-        OrthoCamera { base: base }
+        OrthoCamera { base }
     }
 }
 
 impl Camera for OrthoCamera {
-    fn rotate_localy(&mut self, _d: f64, _v: &Vec3) {
-        unimplemented!();
+    fn rotate_localy(&mut self, d: Real, v: &Vec3) {
+        self.base.rotate_localy(d, v);
     }
 
-    fn get_ray(&self, x: f64, y: f64) -> Ray3 {
-        let screen_point = &(&self.base.screen_x_axis * (x * self.base.screen_ratio))
-            + &(&self.base.screen_y_axis * y);
-        Ray3::new(&screen_point, &self.base.screen_z_axis)
+    fn orbit(&mut self, target: &Vec3, d: Real, axis: &Vec3) {
+        self.base.orbit(target, d, axis);
+    }
+
+    fn get_ray(&self, x: Real, y: Real, sampler: &mut dyn Sampler) -> Ray3 {
+        let screen_point = &(&(&self.base.screen_x_axis * (x * self.base.screen_ratio))
+            + &(&self.base.screen_y_axis * y))
+            + &self.base.location;
+        Ray3::new(screen_point, self.base.screen_z_axis)
+            .with_time(self.base.get_time(sampler.get_1d()))
     }
 }
 
@@ -65,20 +146,24 @@ pub struct PerspectiveCamera {
 
 impl PerspectiveCamera {
     pub fn new(base: Base) -> Self {
-        PerspectiveCamera { base: base }
+        PerspectiveCamera { base }
     }
 }
 
 impl Camera for PerspectiveCamera {
-    fn rotate_localy(&mut self, _d: f64, _v: &Vec3) {
-        unimplemented!();
+    fn rotate_localy(&mut self, d: Real, v: &Vec3) {
+        self.base.rotate_localy(d, v);
+    }
+
+    fn orbit(&mut self, target: &Vec3, d: Real, axis: &Vec3) {
+        self.base.orbit(target, d, axis);
     }
 
-    fn get_ray(&self, x: f64, y: f64) -> Ray3 {
+    fn get_ray(&self, x: Real, y: Real, sampler: &mut dyn Sampler) -> Ray3 {
         let screen_point = &(&(&self.base.screen_x_axis * (x * self.base.screen_ratio))
             + &(&self.base.screen_y_axis * y))
             + &self.base.screen_z_axis;
         // let screen_point = screen_point.normalized();
-        Ray3::new(&self.base.location, &screen_point)
+        Ray3::new(self.base.location, screen_point).with_time(self.base.get_time(sampler.get_1d()))
     }
 }
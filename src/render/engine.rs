@@ -1,16 +1,22 @@
-use super::super::math::triangle::Triangle;
 use super::camera::Camera;
+use super::hit::Hitable;
 use super::kernel::Kernel;
-use super::vertex::Vertex;
+use super::renderer::Renderer;
+use super::sampler::Sampler;
 use num_cpus;
 use std::sync::{Arc, RwLock};
 
 pub struct Data {
     pub view_port_dimension: (u32, u32),
-    pub samples: u8,
-    pub triangles: Vec<Triangle>,
-    pub vertices: Vec<Vertex>,
-    pub cameras: Vec<Box<Camera>>,
+    /// Decides how many samples each pixel gets and where they go, every
+    /// rendering thread works on its own copy.
+    pub sampler: Box<dyn Sampler>,
+    /// Every random decision of a render is derived from it, so the same
+    /// seed gives the same image whatever the number of threads.
+    pub seed: u64,
+    pub world: Box<dyn Hitable + Send + Sync>,
+    pub renderer: Renderer,
+    pub cameras: Vec<Box<dyn Camera>>,
 }
 
 pub struct CpuEngine {
@@ -20,15 +26,20 @@ pub struct CpuEngine {
 
 impl CpuEngine {
     pub fn new(data: Data) -> Self {
+        Self::with_threads(data, num_cpus::get() as u32)
+    }
+
+    /// Splits the rows over `threads_count` kernels instead of one per CPU.
+    /// Glowing surfaces of the world become lights of the renderer.
+    pub fn with_threads(mut data: Data, threads_count: u32) -> Self {
+        data.renderer.add_emitters(data.world.as_ref());
         let data = Arc::new(RwLock::new(data));
+        let threads_count = threads_count.max(1);
         let mut kernels = Vec::new();
-        for i in 0..num_cpus::get() {
-            kernels.push(Kernel::new(&data, i as u32));
-        }
-        CpuEngine {
-            data: data,
-            kernels: kernels,
+        for i in 0..threads_count {
+            kernels.push(Kernel::new(&data, i, threads_count));
         }
+        CpuEngine { data, kernels }
     }
 
     pub fn render(&self) -> Vec<u8> {
@@ -38,7 +49,7 @@ impl CpuEngine {
         let data = self.data.read().unwrap();
         let mut bitmap =
             vec![255u8; (data.view_port_dimension.0 * data.view_port_dimension.1 * 4) as usize];
-        let threads_count = num_cpus::get() as u32;
+        let threads_count = self.kernels.len() as u32;
         let mut bitmap_index = 0;
         let mut starting_row = 0;
         for i in 0..self.kernels.len() {
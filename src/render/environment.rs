@@ -0,0 +1,156 @@
+use std::fmt;
+
+use super::super::math::consts::PI;
+use super::super::math::distribution::Distribution2D;
+use super::super::math::quaternion::Quaternion;
+use super::super::math::sphere::get_uv;
+use super::super::math::vector::{Vec2, Vec3};
+use super::super::math::Real;
+use super::texture::{CubeMap, ImageTexture, Texture};
+
+/// Relative luminance of a linear color.
+fn luminance(c: &Vec3) -> Real {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+/// Unit direction of the latitude longitude coordinates `uv`, the inverse
+/// of `sphere::get_uv`: u goes around +y and v from -y up to +y.
+fn direction_of_uv(uv: &Vec2) -> Vec3 {
+    let theta = uv.y * PI;
+    let phi = uv.x * 2.0 * PI;
+    let sin_theta = theta.sin();
+    Vec3 {
+        x: -sin_theta * phi.cos(),
+        y: -theta.cos(),
+        z: sin_theta * phi.sin(),
+    }
+}
+
+/// An image of the radiance arriving from every direction.
+pub enum EnvironmentMap {
+    /// Latitude longitude image with +y at the top row, the directions the
+    /// texture coordinates of a sphere give.
+    Equirectangular(ImageTexture),
+    CubeMap(CubeMap),
+}
+
+impl EnvironmentMap {
+    /// Color along the unit direction `d` in the space of the map.
+    pub fn value(&self, d: &Vec3) -> Vec3 {
+        match self {
+            EnvironmentMap::Equirectangular(image) => image.value(&get_uv(d), d),
+            EnvironmentMap::CubeMap(cube) => cube.value(d),
+        }
+    }
+
+    /// Columns and rows of the latitude longitude grid the sampling density
+    /// is built on, about a cell for every texel.
+    fn get_grid_size(&self) -> (usize, usize) {
+        match self {
+            EnvironmentMap::Equirectangular(image) => (image.get_width(), image.get_height()),
+            EnvironmentMap::CubeMap(cube) => (4 * cube.get_size(), 2 * cube.get_size()),
+        }
+    }
+}
+
+/// A map infinitely far away that lights the scene from every direction.
+/// Directions are picked by the luminance of the map over a latitude
+/// longitude grid, so small bright parts like the sun of a sky photo get
+/// most of the samples.
+pub struct Environment {
+    map: EnvironmentMap,
+    /// Turns the directions of the map into the world.
+    rotation: Quaternion,
+    /// Scales the radiance of the map.
+    intensity: Real,
+    /// Over the latitude longitude coordinates, weighted by the sine of the
+    /// latitude that squeezes the rows toward the poles.
+    distribution: Distribution2D,
+}
+
+impl Environment {
+    pub fn new(map: EnvironmentMap) -> Self {
+        let (width, height) = map.get_grid_size();
+        let mut func = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let v = (y as Real + 0.5) / height as Real;
+            let sin_theta = (v * PI).sin();
+            for x in 0..width {
+                let uv = Vec2 {
+                    x: (x as Real + 0.5) / width as Real,
+                    y: v,
+                };
+                func.push(luminance(&map.value(&direction_of_uv(&uv))).max(0.0) * sin_theta);
+            }
+        }
+        Self {
+            map,
+            rotation: Quaternion::new(),
+            intensity: 1.0,
+            distribution: Distribution2D::new(&func, width),
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: Quaternion) -> Self {
+        self.rotation = rotation.normalized();
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: Real) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn get_map(&self) -> &EnvironmentMap {
+        &self.map
+    }
+
+    pub fn get_rotation(&self) -> &Quaternion {
+        &self.rotation
+    }
+
+    pub fn get_intensity(&self) -> Real {
+        self.intensity
+    }
+
+    /// Radiance arriving from the unit direction `d` of the world.
+    pub fn radiance(&self, d: &Vec3) -> Vec3 {
+        let local = self.rotation.conjugate().rotate(d);
+        &self.map.value(&local) * self.intensity
+    }
+
+    /// returns: (a unit direction of the world for `u` uniform in the unit
+    /// square, its density per steradian)
+    pub fn sample(&self, u: &Vec2) -> Option<(Vec3, Real)> {
+        let (uv, pdf) = self.distribution.sample(u);
+        let sin_theta = (uv.y * PI).sin();
+        if pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        let wi = self.rotation.rotate(&direction_of_uv(&uv));
+        Some((wi, pdf / (2.0 * PI * PI * sin_theta)))
+    }
+
+    /// Density per steradian of `sample` picking the unit direction `d` of
+    /// the world.
+    pub fn pdf(&self, d: &Vec3) -> Real {
+        let local = self.rotation.conjugate().rotate(d);
+        let uv = get_uv(&local);
+        let sin_theta = (uv.y * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(&uv) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (width, height) = self.map.get_grid_size();
+        f.debug_struct("Environment")
+            .field("grid", &(width, height))
+            .field("rotation", &self.rotation)
+            .field("intensity", &self.intensity)
+            .finish()
+    }
+}
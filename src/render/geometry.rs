@@ -1,33 +1,27 @@
-use std;
 use std::collections::HashMap;
+use std::sync::Arc;
 
-use ::math::vector::{
-    Vec3,
-    Vec2,
-};
-use ::math::aabbox::{
-    AABBox3,
-    ExpandableToPoint3,
-};
-use ::math::matrix::Mat4x4;
-use ::math::ray::Ray3;
-use ::math::triangle::Triangle;
-use ::math::kdtree::KDNode;
-use ::render::mesh::Mesh;
-use ::render::vertex::Vertex;
-use ::render::material::Material;
-use ::io::file::Stream;
-
-// pub trait Geometry {
-//     fn read<E>(&mut self, s: &mut Stream) -> AABBox3<E> where E: VectorElement;
-// }
+use super::super::io::cache::Cache;
+use super::super::io::file::Stream;
+use super::super::math::aabbox::{AABBox3, ExpandableToOther, ExpandableToPoint3};
+use super::super::math::matrix::Mat4x4;
+use super::super::math::ray::Ray3;
+use super::super::math::vector::Vec3;
+use super::super::math::Real;
+use super::hit::{Hitable, Info as HitInfo};
+use super::light::{AreaLight, Light};
+use super::mesh::Mesh;
 
+/// An instance of a group of meshes placed in the world by `transform`.
+/// Mesh data is shared, so the same meshes can be instanced many times.
 pub struct BasicGeometry {
-    pub meshes: Vec<Mesh>,
+    pub meshes: Vec<Arc<Mesh>>,
     pub name_mesh_index: HashMap<String, usize>,
     pub position: Vec3,
-    pub transform: Mat4x4,
-    // TODO add kdtree for your meshes
+    transform: Mat4x4,
+    inverse_transform: Mat4x4,
+    /// Bounds of the meshes in object space.
+    local_bbox: AABBox3,
 }
 
 impl BasicGeometry {
@@ -37,24 +31,83 @@ impl BasicGeometry {
             name_mesh_index: HashMap::new(),
             position: Vec3::new(),
             transform: Mat4x4::new(),
+            inverse_transform: Mat4x4::new(),
+            local_bbox: AABBox3::new(),
+        }
+    }
+
+    /// Another instance of the same meshes placed by `transform`, none when
+    /// it is not invertible.
+    pub fn instance(&self, transform: Mat4x4) -> Option<BasicGeometry> {
+        let mut g = BasicGeometry {
+            meshes: self.meshes.clone(),
+            name_mesh_index: self.name_mesh_index.clone(),
+            position: transform.transform_point(&Vec3::new()),
+            transform: Mat4x4::new(),
+            inverse_transform: Mat4x4::new(),
+            local_bbox: self.local_bbox,
+        };
+        if g.set_transform(transform) {
+            Some(g)
+        } else {
+            None
+        }
+    }
+
+    pub fn add_mesh(&mut self, name: String, mesh: Arc<Mesh>) {
+        if let Some(b) = mesh.bounding_box() {
+            ExpandableToOther::expand(&mut self.local_bbox, &b);
+        }
+        self.name_mesh_index.insert(name, self.meshes.len());
+        self.meshes.push(mesh);
+    }
+
+    /// Bounds follow meshes changed after they were added, like those whose
+    /// vertices were moved by `Mesh::set_vertices`.
+    pub fn update_bounds(&mut self) {
+        self.local_bbox = AABBox3::new();
+        for m in &self.meshes {
+            if let Some(b) = m.bounding_box() {
+                ExpandableToOther::expand(&mut self.local_bbox, &b);
+            }
+        }
+    }
+
+    pub fn get_transform(&self) -> &Mat4x4 {
+        &self.transform
+    }
+
+    /// Returns false and keeps the placement when `transform` is not
+    /// invertible.
+    pub fn set_transform(&mut self, transform: Mat4x4) -> bool {
+        match transform.inverse() {
+            Some(inverse_transform) => {
+                self.inverse_transform = inverse_transform;
+                self.transform = transform;
+                true
+            }
+            None => false,
         }
     }
 
-    pub fn read(&mut self, s: &mut Stream) -> AABBox3 {
+    /// The whole record is read even when its transform is not invertible,
+    /// the geometry can not be placed then and none is returned.
+    pub fn read(&mut self, s: &mut Stream, cache: Option<&Cache>) -> Option<AABBox3> {
         let meshes_count = s.read(&0u8);
         for _ in 0..meshes_count {
-            let mesh_index = self.meshes.len();
             let mesh_name = s.read_string();
-            self.meshes.push(Mesh::new());
+            let mut mesh = Mesh::new();
             if s.read_bool() {
-                let texture_index = s.read(&0u16);
-                self.meshes[mesh_index].read(s, true, true);
+                let _texture_index = s.read(&0u16);
+                mesh.read(s, true, true, cache);
             } else {
-                self.meshes[mesh_index].read(s, true, false);
+                mesh.read(s, true, false, cache);
             }
-            self.name_mesh_index.insert(mesh_name, mesh_index);
+            self.add_mesh(mesh_name, Arc::new(mesh));
         }
-        self.transform.read(s);
+        let mut transform = Mat4x4::new();
+        transform.read(s);
+        let placed = self.set_transform(transform);
         self.position.read(s);
         s.read(&0f32);
         {
@@ -62,57 +115,55 @@ impl BasicGeometry {
             let mut vs = Vec::new();
             let mut aabb = AABBox3::new();
             vs.resize(vertex_count, Vec3::new());
-            for i in 0..vertex_count {
-                vs[i].read(s);
-                aabb.expand(&vs[i]);
+            for v in vs.iter_mut() {
+                v.read(s);
+                ExpandableToPoint3::expand(&mut aabb, v);
             }
             let triangles_count = (s.read(&0u32) / 3) as usize;
-            let mut indices = Vec::new();
-            indices.resize(triangles_count, [0usize; 3]);
-            for i in 0..triangles_count {
-                indices[i] = [
-                    s.read(&0u32) as usize,
-                    s.read(&0u32) as usize,
-                    s.read(&0u32) as usize,
-                ];
+            for _ in 0..(triangles_count * 3) {
+                s.read(&0u32);
+            }
+            if placed {
+                Some(aabb)
+            } else {
+                None
             }
-            aabb
         }
     }
+}
 
-    // distance, position, normal, material
-    pub fn hit(&self, r: &Ray3, tmin: f64) -> Option<(f64, f64, f64, usize)> {
-        // let mut hit = false;
-        let mut distance = tmin;
-        let mut result: Option<(f64, f64, f64, usize)> = None;
-        // TODO i must search through kdtree for mesh finding.
-        for (mesh_index, m) in self.meshes.iter().enumerate() {
-            let hited = m.hit(r, distance);
-            if hited.is_some() {
-                let (d, u, v, _) = hited.unwrap();
-                if d < distance {
-                    result = Some((d, u, v, mesh_index));
-                    distance = d;
-                }
+impl Default for BasicGeometry {
+    fn default() -> Self {
+        BasicGeometry::new()
+    }
+}
+
+impl Hitable for BasicGeometry {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        let (local_ray, t_error) = self.inverse_transform.transform_ray_with_error(r);
+        let mut closest = t_max;
+        let mut result = None;
+        for m in &self.meshes {
+            if let Some(rec) = m.hit(&local_ray, t_min.max(t_error), closest) {
+                closest = rec.t;
+                result = Some(rec);
             }
         }
-        result
+        result.map(|rec: HitInfo| rec.into_world(&self.transform, &self.inverse_transform))
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        Some(self.local_bbox.transformed(&self.transform))
     }
 
-    pub fn get_color(&self, r: &Ray3, tmin: f64) -> Option<Vec3> {
-        let h = self.hit(r, tmin);
-        if h.is_none() {
-            return None;
+    /// Every instance of a glowing mesh gets its own copy in world space.
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Light>>) {
+        for m in &self.meshes {
+            if m.get_material().get_emission().is_some() {
+                if let Some(m) = m.transformed(&self.transform) {
+                    lights.push(Arc::new(AreaLight::new(m)));
+                }
+            }
         }
-        let (d, u, v, mesh_index) = h.unwrap();
-        return None;
     }
 }
-
-// impl<E, T> Geometry<E, T> where E: VectorElement, T: Triangle<E> {
-//     // distance form start point, normal of intersecting triangle, material
-//     fn get_intersection(&self, r: &Ray3<E>) -> Option<(E, Vec3<E>, Material)> {
-//         // TODO
-//         return None;
-//     }
-// }
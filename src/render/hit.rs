@@ -1,14 +1,107 @@
-use super::super::math::vector::Vec3;
+use super::super::math::aabbox::AABBox3;
+use super::super::math::float::offset_ray_origin;
+use super::super::math::matrix::Mat4x4;
 use super::super::math::ray::Ray3;
+use super::super::math::vector::{Vec2, Vec3};
+use super::super::math::Real;
+use super::light::Light;
 use super::material::Material;
+use std::sync::Arc;
+
+/// Shadow rays stop this fraction short of their target, so they do not hit
+/// the surface the target lies on.
+pub const SHADOW_EPSILON: Real = 0.0001;
 
 pub struct Info<'a> {
-    pub t: f64,  
+    pub t: Real,
     pub p: Vec3,
-    pub n: Vec3, 
+    /// Bound of the rounding error of `p` per axis, the true hit point lies
+    /// inside the box it spans around `p`.
+    pub p_error: Vec3,
+    /// Shading normal, smoothed by interpolation on meshes.
+    pub n: Vec3,
+    /// Normal of the actual surface, rays leave along it.
+    pub ng: Vec3,
+    /// Surface parameterization for texture lookups.
+    pub uv: Vec2,
     pub m: &'a dyn Material,
 }
 
+impl<'a> Info<'a> {
+    /// Moves a hit found in object space back to world space, `transform`
+    /// is the object to world matrix and `inverse_transform` its inverse.
+    pub fn into_world(self, transform: &Mat4x4, inverse_transform: &Mat4x4) -> Self {
+        let (p, p_error) = transform.transform_point_with_error(&self.p, &self.p_error);
+        // Rays leaving the point go back through `inverse_transform`, which
+        // rounds their origin again, the offset has to cover that as well.
+        let (_, back_error) = inverse_transform.transform_point_with_error(&p, &Vec3::new());
+        let (_, back_error) = transform.transform_point_with_error(&Vec3::new(), &back_error);
+        let p_error = &p_error + &back_error;
+        Info {
+            t: self.t,
+            p,
+            p_error,
+            n: inverse_transform.transform_normal(&self.n).normalized(),
+            ng: inverse_transform.transform_normal(&self.ng).normalized(),
+            uv: self.uv,
+            m: self.m,
+        }
+    }
+
+    /// Origin for rays leaving the surface toward `w`, far enough from it
+    /// that they can not hit it again right away.
+    pub fn offset_origin(&self, w: &Vec3) -> Vec3 {
+        offset_ray_origin(&self.p, &self.p_error, &self.ng, w)
+    }
+
+    /// A ray leaving the surface in direction `d`.
+    pub fn spawn_ray(&self, d: &Vec3) -> Ray3 {
+        Ray3::new(self.offset_origin(d), *d)
+    }
+
+    /// A shadow ray from the surface to `target`, valid in [0, 1) minus a
+    /// little so it can not hit the surface of the target.
+    pub fn spawn_ray_to(&self, target: &Vec3) -> Ray3 {
+        let o = self.offset_origin(&(target - &self.p));
+        Ray3::with_interval(o, target - &o, 0.0, 1.0 - SHADOW_EPSILON)
+    }
+}
+
 pub trait Hitable {
-    fn hit<'a>(&'a self, r: &Ray3, t_min: f64, t_max: f64) -> Option<Info<'a>>;
-}
\ No newline at end of file
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<Info<'_>>;
+    /// None for unbounded objects.
+    fn bounding_box(&self) -> Option<AABBox3>;
+
+    /// Adds a light for every glowing surface of the object that can be
+    /// sampled, so the renderer aims at them. Surfaces that can not be
+    /// sampled still glow, but only rays that hit them find their light.
+    fn collect_emitters(&self, _lights: &mut Vec<Arc<dyn Light>>) {}
+}
+
+impl<T: Hitable + ?Sized> Hitable for Box<T> {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<Info<'_>> {
+        (**self).hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        (**self).bounding_box()
+    }
+
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Light>>) {
+        (**self).collect_emitters(lights)
+    }
+}
+
+impl<T: Hitable + ?Sized> Hitable for Arc<T> {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<Info<'_>> {
+        (**self).hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        (**self).bounding_box()
+    }
+
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Light>>) {
+        (**self).collect_emitters(lights)
+    }
+}
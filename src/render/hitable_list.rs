@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use super::super::math::aabbox::{AABBox3, ExpandableToOther};
+use super::super::math::ray::Ray3;
+use super::super::math::Real;
+use super::hit::{Hitable, Info};
+use super::light::Light;
+
+pub struct HitableList {
+    pub objects: Vec<Box<dyn Hitable + Send + Sync>>,
+}
+
+impl HitableList {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, o: Box<dyn Hitable + Send + Sync>) {
+        self.objects.push(o);
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+
+impl Default for HitableList {
+    fn default() -> Self {
+        HitableList::new()
+    }
+}
+
+impl Hitable for HitableList {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<Info<'_>> {
+        let mut closest = t_max;
+        let mut result = None;
+        for o in &self.objects {
+            if let Some(rec) = o.hit(r, t_min, closest) {
+                closest = rec.t;
+                result = Some(rec);
+            }
+        }
+        result
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        let mut b = AABBox3::new();
+        for o in &self.objects {
+            b.expand(&o.bounding_box()?);
+        }
+        Some(b)
+    }
+
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Light>>) {
+        for o in &self.objects {
+            o.collect_emitters(lights);
+        }
+    }
+}
@@ -1,5 +1,6 @@
+use super::super::math::vector::Vec3;
+use super::super::math::Real;
 use super::engine::Data;
-use num_cpus;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, RwLock};
 use std::thread::spawn;
@@ -10,11 +11,10 @@ pub struct Kernel {
 }
 
 impl Kernel {
-    pub fn new(data: &Arc<RwLock<Data>>, index: u32) -> Self {
+    pub fn new(data: &Arc<RwLock<Data>>, index: u32, threads_count: u32) -> Self {
         let data = data.clone();
         let (run_signal, run_signal_receiver) = channel();
         let (result_signal, result_receiver) = channel();
-        let threads_count = num_cpus::get() as u32;
         let _ = spawn(move || {
             Kernel::run(
                 data,
@@ -25,8 +25,8 @@ impl Kernel {
             );
         });
         Kernel {
-            run_signal: run_signal,
-            result_receiver: result_receiver,
+            run_signal,
+            result_receiver,
         }
     }
 
@@ -55,34 +55,29 @@ impl Kernel {
                     as usize
             ];
             let mut bitmap_index = 0;
+            let samples_count = data.sampler.get_samples_per_pixel();
+            let width = data.view_port_dimension.0 as Real;
+            let height = data.view_port_dimension.1 as Real;
+            let mut sampler = data.sampler.clone_seeded(data.seed);
             for i in starting_row..ending_row {
                 for j in 0..data.view_port_dimension.0 {
-                    let mut samples = [0u64; 3];
-                    let samples_count = data.samples as i64;
-                    // let samples_count = 8i64;
-                    for si in -samples_count..samples_count + 1 {
-                        for sj in -samples_count..samples_count + 1 {
-                            let x = (((j as f64 + (si as f64 / (samples_count as f64 * 2.0)))
-                                / data.view_port_dimension.0 as f64)
-                                - 0.5) * 1.0; // todo 2
-                            let y = (((i as f64 + (sj as f64 / (samples_count as f64 * 2.0)))
-                                / data.view_port_dimension.1 as f64)
-                                - 0.5) * 1.0;
-                            let ray = data.cameras[0].get_ray(x, y);
-                            let r = data.triangles[0].intersect(&ray, 90000.0, &data.vertices);
-                            if let Some(_) = r {
-                                samples[0] += 255;
-                                samples[1] += 0;
-                                samples[2] += 0;
-                            }
-                        }
+                    let mut color = Vec3::new();
+                    for s in 0..samples_count {
+                        sampler.start_pixel_sample((j, i), s);
+                        let film = sampler.get_2d();
+                        let x = ((j as Real + film.x) / width) - 0.5;
+                        // Rows go downward in the bitmap but upward on the screen.
+                        let y = 0.5 - ((i as Real + film.y) / height);
+                        let ray = data.cameras[0].get_ray(x, y, sampler.as_mut());
+                        color +=
+                            &data
+                                .renderer
+                                .radiance(&ray, data.world.as_ref(), sampler.as_mut());
                     }
-                    bitmap[bitmap_index] =
-                        (samples[0] / (samples_count * samples_count * 4) as u64) as u8;
-                    bitmap[bitmap_index + 1] =
-                        (samples[1] / (samples_count * samples_count * 4) as u64) as u8;
-                    bitmap[bitmap_index + 2] =
-                        (samples[2] / (samples_count * samples_count * 4) as u64) as u8;
+                    color /= samples_count as Real;
+                    bitmap[bitmap_index] = to_byte(color.x);
+                    bitmap[bitmap_index + 1] = to_byte(color.y);
+                    bitmap[bitmap_index + 2] = to_byte(color.z);
                     bitmap_index += 4;
                 }
             }
@@ -98,3 +93,8 @@ impl Drop for Kernel {
         let _ = self.result_receiver.recv().unwrap();
     }
 }
+
+/// Gamma 2 encoding of a linear radiance channel.
+fn to_byte(c: Real) -> u8 {
+    (c.max(0.0).sqrt().min(1.0) * 255.99) as u8
+}
@@ -0,0 +1,378 @@
+use super::super::math::consts::PI;
+use super::super::math::onb::Onb;
+use super::super::math::quad::Quad;
+use super::super::math::ray::Ray3;
+use super::super::math::sampling;
+use super::super::math::sphere::{get_surface_info, Sphere};
+use super::super::math::triangle::Triangle;
+use super::super::math::vector::{Vec2, Vec3};
+use super::super::math::Real;
+use super::hit::{Hitable, Info as HitInfo};
+
+/// Angle the sun covers seen from the earth, in radians.
+pub const SUN_ANGULAR_DIAMETER: Real = 0.00925;
+
+/// A direction toward a light picked for a shaded point.
+pub struct LightSample {
+    /// Unit direction from the point toward the light.
+    pub wi: Vec3,
+    /// Radiance arriving along `wi` when nothing is in between.
+    pub li: Vec3,
+    /// Density of `wi` per steradian, or the probability of the single
+    /// direction of a delta light.
+    pub pdf: Real,
+    /// Ray from the point that must not hit anything for the light to be
+    /// seen.
+    pub shadow: Ray3,
+}
+
+/// Anything that can be sampled for light arriving at a point, so the
+/// integrator can aim at it instead of waiting to hit it by chance.
+pub trait Light: Sync + Send {
+    /// Picks a direction toward the light from the hit point `rec` with
+    /// `u` uniform in the unit square. None when the light can not reach it.
+    /// Lights that are not delta give every direction they pick even when
+    /// their own radiance there is zero, its density is part of the mix
+    /// `pdf_li` is counted in and what the direction reaches decides.
+    fn sample_li(&self, rec: &HitInfo, u: &Vec2) -> Option<LightSample>;
+
+    /// Density per steradian with which `sample_li` picks the unit
+    /// direction `wi` from `rec`.
+    fn pdf_li(&self, rec: &HitInfo, wi: &Vec3) -> Real;
+
+    /// Radiance arriving along the unit direction `d` on a ray that hits
+    /// nothing, only lights infinitely far away give any. Lights with a
+    /// place in the world give theirs through the surfaces rays hit.
+    fn le(&self, _d: &Vec3) -> Vec3 {
+        Vec3::new()
+    }
+
+    /// Lights that arrive from a single direction, like a point, can only
+    /// be reached by sampling them.
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+/// Radiant intensity spread evenly in every direction from `position`.
+pub struct PointLight {
+    pub position: Vec3,
+    pub intensity: Vec3,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Vec3) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, rec: &HitInfo, _u: &Vec2) -> Option<LightSample> {
+        let to_light = &self.position - &rec.p;
+        let distance2 = to_light.squared_length();
+        if distance2 <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi: &to_light / distance2.sqrt(),
+            li: &self.intensity / distance2,
+            pdf: 1.0,
+            shadow: rec.spawn_ray_to(&self.position),
+        })
+    }
+
+    fn pdf_li(&self, _rec: &HitInfo, _wi: &Vec3) -> Real {
+        0.0
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+/// A point light that only shines inside a cone around `direction`, at
+/// full intensity up to `cos_falloff_start` and fading out to nothing at
+/// `cos_total_width`.
+pub struct SpotLight {
+    pub position: Vec3,
+    /// Unit axis of the cone.
+    pub direction: Vec3,
+    pub intensity: Vec3,
+    cos_total_width: Real,
+    cos_falloff_start: Real,
+}
+
+impl SpotLight {
+    /// `total_width` and `falloff_start` are the half angles of the cone in
+    /// radians, the second one no larger than the first.
+    pub fn new(
+        position: Vec3,
+        direction: &Vec3,
+        intensity: Vec3,
+        total_width: Real,
+        falloff_start: Real,
+    ) -> Self {
+        Self {
+            position,
+            direction: direction.normalized(),
+            intensity,
+            cos_total_width: total_width.cos(),
+            cos_falloff_start: falloff_start.min(total_width).cos(),
+        }
+    }
+
+    /// Fraction of the intensity given off along the unit direction `w`.
+    fn falloff(&self, w: &Vec3) -> Real {
+        let cos_theta = w.dot(&self.direction);
+        if cos_theta <= self.cos_total_width {
+            return 0.0;
+        }
+        if cos_theta >= self.cos_falloff_start {
+            return 1.0;
+        }
+        let delta =
+            (cos_theta - self.cos_total_width) / (self.cos_falloff_start - self.cos_total_width);
+        let delta2 = delta * delta;
+        delta2 * delta2
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, rec: &HitInfo, _u: &Vec2) -> Option<LightSample> {
+        let to_light = &self.position - &rec.p;
+        let distance2 = to_light.squared_length();
+        if distance2 <= 0.0 {
+            return None;
+        }
+        let wi = &to_light / distance2.sqrt();
+        let falloff = self.falloff(&-&wi);
+        if falloff <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            li: &self.intensity * (falloff / distance2),
+            pdf: 1.0,
+            shadow: rec.spawn_ray_to(&self.position),
+        })
+    }
+
+    fn pdf_li(&self, _rec: &HitInfo, _wi: &Vec3) -> Real {
+        0.0
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+/// Light from a disk infinitely far away, like the sun's. It arrives from
+/// inside a cone around `direction` whose angular diameter softens the
+/// shadows, or from exactly `direction` when the diameter is zero.
+pub struct SunLight {
+    /// Unit direction from the scene toward the sun.
+    pub direction: Vec3,
+    /// Irradiance on a surface facing the sun, the same whatever its size.
+    pub irradiance: Vec3,
+    cos_theta_max: Real,
+}
+
+impl SunLight {
+    pub fn new(direction: &Vec3, irradiance: Vec3, angular_diameter: Real) -> Self {
+        Self {
+            direction: direction.normalized(),
+            irradiance,
+            cos_theta_max: (0.5 * angular_diameter).cos(),
+        }
+    }
+
+    /// Radiance of every direction of the disk, its irradiance spread over
+    /// the solid angle it covers.
+    fn get_radiance(&self) -> Vec3 {
+        &self.irradiance / (2.0 * PI * (1.0 - self.cos_theta_max))
+    }
+}
+
+impl Light for SunLight {
+    fn sample_li(&self, rec: &HitInfo, u: &Vec2) -> Option<LightSample> {
+        if self.is_delta() {
+            return Some(LightSample {
+                wi: self.direction,
+                li: self.irradiance,
+                pdf: 1.0,
+                shadow: rec.spawn_ray(&self.direction),
+            });
+        }
+        let wi = Onb::from_w(&self.direction)
+            .local_to_world(&sampling::uniform_cone(u, self.cos_theta_max));
+        Some(LightSample {
+            wi,
+            li: self.get_radiance(),
+            pdf: sampling::uniform_cone_pdf(self.cos_theta_max),
+            shadow: rec.spawn_ray(&wi),
+        })
+    }
+
+    fn pdf_li(&self, _rec: &HitInfo, wi: &Vec3) -> Real {
+        if self.is_delta() || wi.dot(&self.direction) < self.cos_theta_max {
+            return 0.0;
+        }
+        sampling::uniform_cone_pdf(self.cos_theta_max)
+    }
+
+    fn le(&self, d: &Vec3) -> Vec3 {
+        if self.is_delta() || d.dot(&self.direction) < self.cos_theta_max {
+            return Vec3::new();
+        }
+        self.get_radiance()
+    }
+
+    fn is_delta(&self) -> bool {
+        self.cos_theta_max >= 1.0
+    }
+}
+
+/// A surface area lights can pick points of.
+pub trait Shape: Hitable + Sync + Send {
+    /// Picks a point of the surface seen from `rec` with `u` uniform in the
+    /// unit square, returns its hit information and the density per
+    /// steradian of the direction toward it.
+    fn sample_from(&self, rec: &HitInfo, u: &Vec2) -> Option<(HitInfo<'_>, Real)>;
+
+    /// Density per steradian of `sample_from` picking a point the unit
+    /// direction `wi` from `rec` reaches.
+    fn pdf_from(&self, rec: &HitInfo, wi: &Vec3) -> Real;
+}
+
+/// Turns the density per area of picking `point`, on a surface with the
+/// normal `n`, into one per steradian of the direction from `p`.
+pub fn area_to_solid_angle(p: &Vec3, point: &Vec3, n: &Vec3, pdf_area: Real) -> Real {
+    let to_point = point - p;
+    let distance2 = to_point.squared_length();
+    let cos_theta = n.dot(&to_point).abs() / distance2.sqrt();
+    if cos_theta <= 0.0 {
+        return 0.0;
+    }
+    pdf_area * distance2 / cos_theta
+}
+
+/// Density of uniform area sampling for the first point `wi` reaches.
+fn hit_pdf<S: Shape + ?Sized>(shape: &S, rec: &HitInfo, wi: &Vec3, area: Real) -> Real {
+    let r = rec.spawn_ray(wi);
+    match shape.hit(&r, r.t_min, r.t_max) {
+        Some(hit) => area_to_solid_angle(&rec.p, &hit.p, &hit.ng, 1.0 / area),
+        None => 0.0,
+    }
+}
+
+/// Seen from outside only the cone of directions toward the sphere is
+/// sampled, from inside every point of it is.
+impl Shape for Sphere {
+    fn sample_from(&self, rec: &HitInfo, u: &Vec2) -> Option<(HitInfo<'_>, Real)> {
+        let to_center = &self.center - &rec.p;
+        let distance2 = to_center.squared_length();
+        let radius2 = self.radius * self.radius;
+        if distance2 <= radius2 {
+            let n = sampling::uniform_sphere(u);
+            let info = get_surface_info(&self.center, self.radius, &n, 0.0, self.material.as_ref());
+            let pdf = area_to_solid_angle(&rec.p, &info.p, &n, 1.0 / (4.0 * PI * radius2));
+            return Some((info, pdf));
+        }
+        let distance = distance2.sqrt();
+        let sin2_theta_max = radius2 / distance2;
+        let cos_theta_max = (1.0 - sin2_theta_max).max(0.0).sqrt();
+        let cos_theta = 1.0 - u.x * (1.0 - cos_theta_max);
+        let sin2_theta = 1.0 - cos_theta * cos_theta;
+        // The angle at the center between the point and the direction back
+        // toward `rec`, from the sine rule in their triangle.
+        let cos_alpha = sin2_theta / sin2_theta_max.sqrt()
+            + cos_theta * (1.0 - sin2_theta / sin2_theta_max).max(0.0).sqrt();
+        let sin_alpha = (1.0 - cos_alpha * cos_alpha).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.y;
+        let n = Onb::from_w(&(&to_center / -distance)).local_to_world(&Vec3 {
+            x: sin_alpha * phi.cos(),
+            y: sin_alpha * phi.sin(),
+            z: cos_alpha,
+        });
+        let info = get_surface_info(&self.center, self.radius, &n, 0.0, self.material.as_ref());
+        Some((info, sampling::uniform_cone_pdf(cos_theta_max)))
+    }
+
+    fn pdf_from(&self, rec: &HitInfo, wi: &Vec3) -> Real {
+        let distance2 = (&self.center - &rec.p).squared_length();
+        let radius2 = self.radius * self.radius;
+        if distance2 <= radius2 {
+            return hit_pdf(self, rec, wi, 4.0 * PI * radius2);
+        }
+        let r = rec.spawn_ray(wi);
+        if self.hit(&r, r.t_min, r.t_max).is_none() {
+            return 0.0;
+        }
+        let cos_theta_max = (1.0 - radius2 / distance2).max(0.0).sqrt();
+        sampling::uniform_cone_pdf(cos_theta_max)
+    }
+}
+
+impl Shape for Quad {
+    fn sample_from(&self, rec: &HitInfo, u: &Vec2) -> Option<(HitInfo<'_>, Real)> {
+        let info = self.get_hit_info(0.0, u.x, u.y);
+        let pdf = area_to_solid_angle(&rec.p, &info.p, &info.ng, 1.0 / self.get_area());
+        Some((info, pdf))
+    }
+
+    fn pdf_from(&self, rec: &HitInfo, wi: &Vec3) -> Real {
+        hit_pdf(self, rec, wi, self.get_area())
+    }
+}
+
+impl Shape for Triangle {
+    fn sample_from(&self, rec: &HitInfo, u: &Vec2) -> Option<(HitInfo<'_>, Real)> {
+        let info = self.sample_point(u);
+        let pdf = area_to_solid_angle(&rec.p, &info.p, &info.ng, 1.0 / self.get_area());
+        Some((info, pdf))
+    }
+
+    fn pdf_from(&self, rec: &HitInfo, wi: &Vec3) -> Real {
+        hit_pdf(self, rec, wi, self.get_area())
+    }
+}
+
+/// Light given off by the material of `shape`, like a `DiffuseLight`. The
+/// shape only picks the points, what rays hit is the glowing surface in the
+/// world, so `Hitable::collect_emitters` makes these for every one of them.
+pub struct AreaLight<S: Shape> {
+    pub shape: S,
+}
+
+impl<S: Shape> AreaLight<S> {
+    pub fn new(shape: S) -> Self {
+        Self { shape }
+    }
+}
+
+impl<S: Shape> Light for AreaLight<S> {
+    fn sample_li(&self, rec: &HitInfo, u: &Vec2) -> Option<LightSample> {
+        let (info, pdf) = self.shape.sample_from(rec, u)?;
+        let to_light = &info.p - &rec.p;
+        let distance2 = to_light.squared_length();
+        if pdf <= 0.0 || distance2 <= 0.0 {
+            return None;
+        }
+        let wi = &to_light / distance2.sqrt();
+        // A point seen from its back side gives no light, but the traced
+        // direction may still reach another light in front of it.
+        Some(LightSample {
+            wi,
+            li: info.m.emitted(&info, &-&wi),
+            pdf,
+            shadow: rec.spawn_ray_to(&info.p),
+        })
+    }
+
+    fn pdf_li(&self, rec: &HitInfo, wi: &Vec3) -> Real {
+        self.shape.pdf_from(rec, wi)
+    }
+}
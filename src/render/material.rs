@@ -1,17 +1,121 @@
+use std::ops::BitOr;
+use std::sync::Arc;
+
+use super::super::math::consts::FRAC_1_PI;
+use super::super::math::onb::Onb;
 use super::super::math::ray::Ray3;
-use super::super::math::vector::Vec3;
+use super::super::math::sampling;
+use super::super::math::vector::{Vec2, Vec3};
+use super::super::math::Real;
 use super::hit::Info as HitInfo;
-use rand::{thread_rng, Rng};
+use super::sampler::Sampler;
+use super::texture::Texture;
 
-fn schlick(cosine: f64, ref_idx: f64) -> f64 {
+fn schlick(cosine: Real, ref_idx: Real) -> Real {
     let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     let r0 = r0 * r0;
-    return r0 + (1.0 - r0) * (1.0 - cosine).powi(5);
+    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
 
-pub trait Material {
-    /// returns: (attenuation, scattered)
-    fn scatter(&self, r_in: &Ray3, rec: &HitInfo) -> Option<(Vec3, Ray3)>;
+/// What a sampled direction was scattered by, a set of flags combined
+/// with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lobe(u8);
+
+impl Lobe {
+    pub const REFLECTION: Lobe = Lobe(1);
+    pub const TRANSMISSION: Lobe = Lobe(2);
+    pub const DIFFUSE: Lobe = Lobe(4);
+    pub const GLOSSY: Lobe = Lobe(8);
+    /// Directions `eval` and `pdf` know nothing about, a single mirror or
+    /// refraction direction or a lobe sampled without a known density.
+    /// Light sampling can not find them, only following the sample does.
+    pub const SPECULAR: Lobe = Lobe(16);
+
+    pub fn contains(&self, o: Lobe) -> bool {
+        self.0 & o.0 == o.0
+    }
+
+    pub fn is_specular(&self) -> bool {
+        self.contains(Lobe::SPECULAR)
+    }
+}
+
+impl BitOr for Lobe {
+    type Output = Lobe;
+    fn bitor(self, o: Lobe) -> Lobe {
+        Lobe(self.0 | o.0)
+    }
+}
+
+/// A direction picked by `Material::sample`.
+pub struct MaterialSample {
+    /// Unit direction the light arrives from, away from the surface.
+    pub wi: Vec3,
+    /// `eval(wo, wi) * |cos(wi)| / pdf`, what the path throughput is
+    /// multiplied by.
+    pub weight: Vec3,
+    /// Density per steradian of `wi`, or the probability of picking the
+    /// lobe for specular ones.
+    pub pdf: Real,
+    pub lobe: Lobe,
+}
+
+/// Directions are unit vectors in world space that point away from the
+/// surface, `wo` toward the viewer and `wi` toward the light, and the frame
+/// is the shading normal of `rec`.
+pub trait Material: Sync + Send {
+    /// Picks the direction light arrives from for light leaving toward
+    /// `wo`, with `uc` uniform in [0, 1) choosing among lobes and `u`
+    /// uniform in the unit square choosing inside one. None when the light
+    /// is absorbed.
+    fn sample(&self, rec: &HitInfo, wo: &Vec3, uc: Real, u: &Vec2) -> Option<MaterialSample>;
+
+    /// Fraction of the light arriving from `wi` that leaves toward `wo`,
+    /// per steradian and without the cosine term. Zero for specular lobes.
+    fn eval(&self, _rec: &HitInfo, _wo: &Vec3, _wi: &Vec3) -> Vec3 {
+        Vec3::new()
+    }
+
+    /// Density per steradian of `sample` picking `wi` for `wo`, zero for
+    /// specular lobes.
+    fn pdf(&self, _rec: &HitInfo, _wo: &Vec3, _wi: &Vec3) -> Real {
+        0.0
+    }
+
+    /// Radiance the surface gives off toward `wo` by itself.
+    fn emitted(&self, _rec: &HitInfo, _wo: &Vec3) -> Vec3 {
+        Vec3::new()
+    }
+
+    /// The emission of glowing materials, which `Hitable::collect_emitters`
+    /// gives to copies of the surfaces it makes lights of.
+    fn get_emission(&self) -> Option<&DiffuseLight> {
+        None
+    }
+
+    /// returns: (attenuation, scattered), every random choice is taken from
+    /// `sampler`. Kept for integrators that only follow paths.
+    fn scatter(
+        &self,
+        r_in: &Ray3,
+        rec: &HitInfo,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Vec3, Ray3)> {
+        let wo = -&r_in.d.normalized();
+        let uc = sampler.get_1d();
+        let s = self.sample(rec, &wo, uc, &sampler.get_2d())?;
+        Some((s.weight, rec.spawn_ray(&s.wi).with_time(r_in.time)))
+    }
+}
+
+/// The shading normal flipped to the side `wo` leaves on.
+fn facing_normal(rec: &HitInfo, wo: &Vec3) -> Vec3 {
+    if wo.dot(&rec.n) < 0.0 {
+        -&rec.n
+    } else {
+        rec.n
+    }
 }
 
 pub struct Lambertian {
@@ -25,81 +129,161 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, r_in: &Ray3, rec: &HitInfo) -> Option<(Vec3, Ray3)> {
-        let target = &rec.p + &(&rec.n + &Vec3::random_in_unit_sphere());
-        Some((self.albedo, Ray3::new(rec.p, &target - &rec.p)))
+    fn sample(&self, rec: &HitInfo, wo: &Vec3, _uc: Real, u: &Vec2) -> Option<MaterialSample> {
+        let onb = Onb::from_w(&facing_normal(rec, wo));
+        let local = sampling::cosine_hemisphere(u);
+        if local.z <= 0.0 {
+            return None;
+        }
+        Some(MaterialSample {
+            wi: onb.local_to_world(&local),
+            weight: self.albedo,
+            pdf: sampling::cosine_hemisphere_pdf(local.z),
+            lobe: Lobe::DIFFUSE | Lobe::REFLECTION,
+        })
+    }
+
+    fn eval(&self, rec: &HitInfo, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if wi.dot(&facing_normal(rec, wo)) <= 0.0 {
+            return Vec3::new();
+        }
+        &self.albedo * FRAC_1_PI
+    }
+
+    fn pdf(&self, rec: &HitInfo, wo: &Vec3, wi: &Vec3) -> Real {
+        sampling::cosine_hemisphere_pdf(wi.dot(&facing_normal(rec, wo)))
     }
 }
 
 pub struct Metal {
     pub albedo: Vec3,
-    pub fuzz: f64,
+    pub fuzz: Real,
 }
 
 impl Metal {
-    pub fn new(albedo: Vec3, fuzz: f64) -> Self {
+    pub fn new(albedo: Vec3, fuzz: Real) -> Self {
         Self { albedo, fuzz }
     }
 }
 
+/// The fuzzy reflection has no density that is cheap to evaluate, so it is
+/// sampled like a mirror.
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray3, rec: &HitInfo) -> Option<(Vec3, Ray3)> {
-        let reflected = r_in.d.normalized().reflect(&rec.n);
-        let scattered = Ray3::new(
-            rec.p,
-            &reflected + &(&Vec3::random_in_unit_sphere() * self.fuzz),
-        );
-        let attenuation = self.albedo;
-        if scattered.d.dot(&rec.n) > 0.0 {
-            return Some((attenuation, scattered));
+    fn sample(&self, rec: &HitInfo, wo: &Vec3, uc: Real, u: &Vec2) -> Option<MaterialSample> {
+        let reflected = (-wo).reflect(&rec.n);
+        let fuzz = &sampling::uniform_ball(u, uc) * self.fuzz;
+        let wi = &reflected + &fuzz;
+        if wi.dot(&rec.n) <= 0.0 {
+            return None;
         }
-        None
+        Some(MaterialSample {
+            wi: wi.normalized(),
+            weight: self.albedo,
+            pdf: 1.0,
+            lobe: Lobe::SPECULAR | Lobe::REFLECTION,
+        })
     }
 }
 
 pub struct Dielectric {
-    pub ref_idx: f64,
+    pub ref_idx: Real,
 }
 
 impl Dielectric {
-    pub fn new(ref_idx: f64) -> Self {
+    pub fn new(ref_idx: Real) -> Self {
         Self { ref_idx }
     }
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray3, rec: &HitInfo) -> Option<(Vec3, Ray3)> {
-        let attenuation = Vec3 {
-            x: 1.0,
-            y: 1.0,
-            z: 1.0,
-        };
-        let (outward_normal, ni_over_nt, cosine) = if r_in.d.dot(&rec.n) > 0.0 {
-            let cosine = r_in.d.dot(&rec.n) / r_in.d.length();
+    fn sample(&self, rec: &HitInfo, wo: &Vec3, uc: Real, _u: &Vec2) -> Option<MaterialSample> {
+        let d = -wo;
+        let cosine = d.dot(&rec.n);
+        let (outward_normal, ni_over_nt, cosine) = if cosine > 0.0 {
             (
                 -&rec.n,
                 self.ref_idx,
                 (1.0 - self.ref_idx * self.ref_idx * (1.0 - cosine * cosine)).sqrt(),
             )
         } else {
-            (
-                rec.n,
-                1.0 / self.ref_idx,
-                -r_in.d.dot(&rec.n) / r_in.d.length(),
-            )
+            (rec.n, 1.0 / self.ref_idx, -cosine)
         };
-        let reflected = r_in.d.reflect(&rec.n);
-        let (refracted, reflect_prob) =
-            if let Some(refracted) = r_in.d.refract(&outward_normal, ni_over_nt) {
-                (refracted, schlick(cosine, self.ref_idx))
-            } else {
-                (Vec3::new(), 1.0)
-            };
-        let scattered = if thread_rng().gen_range(-1.0f64, 1.0f64) < reflect_prob {
-            Ray3::new(rec.p, reflected)
-        } else {
-            Ray3::new(rec.p, refracted)
+        let (refracted, reflect_prob) = match d.refract(&outward_normal, ni_over_nt) {
+            Some(refracted) => (refracted, schlick(cosine, self.ref_idx)),
+            None => (Vec3::new(), 1.0),
         };
-        Some((attenuation, scattered))
+        let white = Vec3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        };
+        // Choosing reflection with its Fresnel probability cancels the
+        // Fresnel factor out of the weight.
+        Some(if uc < reflect_prob {
+            MaterialSample {
+                wi: d.reflect(&rec.n),
+                weight: white,
+                pdf: reflect_prob,
+                lobe: Lobe::SPECULAR | Lobe::REFLECTION,
+            }
+        } else {
+            MaterialSample {
+                wi: refracted.normalized(),
+                weight: white,
+                pdf: 1.0 - reflect_prob,
+                lobe: Lobe::SPECULAR | Lobe::TRANSMISSION,
+            }
+        })
+    }
+}
+
+/// Gives off the same radiance in every direction and absorbs all the light
+/// arriving. With a texture the radiance is scaled by its color.
+#[derive(Clone)]
+pub struct DiffuseLight {
+    pub radiance: Vec3,
+    pub texture: Option<Arc<dyn Texture>>,
+    /// Glows on the back side as well, the side the geometric normal
+    /// points away from.
+    pub two_sided: bool,
+}
+
+impl DiffuseLight {
+    pub fn new(radiance: Vec3) -> Self {
+        Self {
+            radiance,
+            texture: None,
+            two_sided: false,
+        }
+    }
+
+    pub fn with_texture(mut self, texture: Arc<dyn Texture>) -> Self {
+        self.texture = Some(texture);
+        self
+    }
+
+    pub fn with_two_sided(mut self, two_sided: bool) -> Self {
+        self.two_sided = two_sided;
+        self
+    }
+}
+
+impl Material for DiffuseLight {
+    fn sample(&self, _rec: &HitInfo, _wo: &Vec3, _uc: Real, _u: &Vec2) -> Option<MaterialSample> {
+        None
+    }
+
+    fn emitted(&self, rec: &HitInfo, wo: &Vec3) -> Vec3 {
+        if !self.two_sided && wo.dot(&rec.ng) <= 0.0 {
+            return Vec3::new();
+        }
+        match self.texture {
+            Some(ref texture) => &self.radiance * &texture.value(&rec.uv, &rec.p),
+            None => self.radiance,
+        }
+    }
+
+    fn get_emission(&self) -> Option<&DiffuseLight> {
+        Some(self)
     }
 }
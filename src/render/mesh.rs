@@ -1,56 +1,609 @@
-use ::io::file::Stream;
-use ::math::kdtree::KDNode;
-use ::math::ray::Ray3;
-use ::math::triangle::Triangle;
-use ::math::vector::Vec3;
-use ::render::material::Material;
-use ::render::vertex::Vertex;
+use std::sync::Arc;
+
+use super::super::io::cache::{Cache, ContentHash};
+use super::super::io::file::Stream;
+use super::super::math::aabbox::AABBox3;
+use super::super::math::bvh::Bvh;
+use super::super::math::float::next_up;
+use super::super::math::kdtree::KDNode;
+use super::super::math::matrix::Mat4x4;
+use super::super::math::ray::Ray3;
+use super::super::math::sampling;
+use super::super::math::tessellator::PhongTriangle;
+use super::super::math::triangle::{MeshData, Triangle};
+use super::super::math::vector::{Vec2, Vec3};
+use super::super::math::Real;
+use super::hit::{Hitable, Info as HitInfo};
+use super::light::{area_to_solid_angle, AreaLight, Light, Shape};
+use super::material::{Lambertian, Material};
+use super::vertex::Vertex;
+
+/// Triangles per leaf of the mesh trees.
+const LEAF_SIZE: usize = 4;
 
 pub struct Mesh {
-    vertices:  Vec<Vertex>,
-    triangles: Vec<Triangle>,
-    material:  Material,
-    kdtree:    Box<KDNode>,
+    /// Shared with every triangle of the mesh, which only keeps the
+    /// indices of its vertices.
+    data: Arc<MeshData>,
+    triangles: Bvh<Triangle>,
+    /// Hits the flat triangles through `kdtree` instead of their BVH.
+    use_kdtree: bool,
+    kdtree: Option<Box<KDNode>>,
+    /// Cost of the tree of the flat triangles when it was last built.
+    built_cost: Real,
+    /// Alpha of the Phong tessellation, None renders the flat triangles.
+    phong_alpha: Option<Real>,
+    curved: Option<Bvh<PhongTriangle>>,
+    /// Index in `triangles` of every curved triangle.
+    curved_order: Vec<usize>,
+    /// Running sum of the areas of the flat triangles in the order of the
+    /// tree, picks them by area when the mesh is a light.
+    areas: Vec<Real>,
 }
 
 impl Mesh {
     pub fn new() -> Mesh {
         Mesh {
-            vertices:  Vec::new(),
-            triangles: Vec::new(),
-            material:  Material::new(),
-            kdtree:    Box::new(KDNode::new()),
+            data: Arc::new(MeshData {
+                vertices: Vec::new(),
+                material: Arc::new(Lambertian::new(Vec3 {
+                    x: 0.5,
+                    y: 0.5,
+                    z: 0.5,
+                })),
+            }),
+            triangles: Bvh::new_linear(Vec::new(), LEAF_SIZE, false),
+            use_kdtree: false,
+            kdtree: None,
+            built_cost: 0.0,
+            phong_alpha: None,
+            curved: None,
+            curved_order: Vec::new(),
+            areas: Vec::new(),
         }
     }
 
-    pub fn read(&mut self, s: &mut Stream, has_normal: bool, has_uv: bool) {
-        let vertex_count = (s.read(&0u32) as usize) / 8;  // TODO
-        self.vertices.resize(vertex_count, Vertex::new());
-        for i in 0..vertex_count {
-            self.vertices[i].read(s, has_normal, has_uv);
+    /// With a `cache` the tree is loaded from it when it holds one for the
+    /// same positions and indices, otherwise the built one is saved there.
+    pub fn read(&mut self, s: &mut Stream, has_normal: bool, has_uv: bool, cache: Option<&Cache>) {
+        let vertex_count = (s.read(&0u32) as usize) / 8; // TODO
+        let mut vertices = vec![Vertex::new(); vertex_count];
+        for v in &mut vertices {
+            v.read(s, has_normal, has_uv);
         }
         let triangles_count = (s.read(&0u32) / 3) as usize;
-        let mut indices = Vec::new();
-        indices.resize(triangles_count, [0usize; 3]);
-        for i in 0..triangles_count {
-            indices[i] = [
-                s.read(&0u32) as usize,
-                s.read(&0u32) as usize,
-                s.read(&0u32) as usize,
-            ];
-        }
-        self.triangles = Vec::new();
-        self.triangles.resize(triangles_count, Triangle::new(&indices[0], &self.vertices));
-        let mut triangle_indices = Vec::new();
-        triangle_indices.resize(triangles_count, 0usize);
-        for i in 1..triangles_count {
-            self.triangles[i] = Triangle::new(&indices[i], &self.vertices);
-            triangle_indices[i] = i;
-        }
-        self.kdtree = KDNode::build(&triangle_indices, &self.vertices, &self.triangles).unwrap();
-    }
-
-    pub fn hit(&self, r: &Ray3, tmin: f64) -> Option<(f64, f64, f64, usize)> {
-        return KDNode::hit(&*self.kdtree, r, tmin, &self.vertices, &self.triangles);
+        let indices: Vec<[usize; 3]> = (0..triangles_count)
+            .map(|_| {
+                [
+                    s.read(&0u32) as usize,
+                    s.read(&0u32) as usize,
+                    s.read(&0u32) as usize,
+                ]
+            })
+            .collect();
+        self.set_data(vertices, &indices, cache);
+    }
+
+    pub fn set_data(
+        &mut self,
+        vertices: Vec<Vertex>,
+        indices: &[[usize; 3]],
+        cache: Option<&Cache>,
+    ) {
+        self.share(vertices, self.data.material.clone());
+        self.build_triangles(indices, cache);
+    }
+
+    /// Replaces the data the triangles share, they have to be made again
+    /// to point at it.
+    fn share(&mut self, vertices: Vec<Vertex>, material: Arc<dyn Material>) {
+        self.data = Arc::new(MeshData { vertices, material });
+    }
+
+    /// Vertex indices of every triangle in the order of the tree.
+    fn get_indices(&self) -> Vec<[usize; 3]> {
+        self.triangles
+            .objects
+            .iter()
+            .map(|t| t.get_indices())
+            .collect()
+    }
+
+    /// Makes every triangle again for the current data, the tree stays as
+    /// it is.
+    fn update_triangles(&mut self) {
+        for t in &mut self.triangles.objects {
+            *t = Triangle::new(&t.get_indices(), &self.data);
+        }
+    }
+
+    fn build_triangles(&mut self, indices: &[[usize; 3]], cache: Option<&Cache>) {
+        let triangles: Vec<Triangle> = indices
+            .iter()
+            .map(|inds| Triangle::new(inds, &self.data))
+            .collect();
+        let count = triangles.len();
+        let (tree, _) = match cache {
+            Some(cache) => {
+                let hash = self.get_content_hash(indices);
+                match cache.load_tree(hash, count) {
+                    Some((nodes, order)) => (Bvh::from_order(nodes, triangles, &order), order),
+                    None => {
+                        let (tree, order) = Bvh::new_linear_with_order(triangles, LEAF_SIZE, true);
+                        cache.store_tree(hash, &tree.nodes, &order, count);
+                        (tree, order)
+                    }
+                }
+            }
+            None => Bvh::new_linear_with_order(triangles, LEAF_SIZE, true),
+        };
+        self.built_cost = tree.get_cost();
+        self.triangles = tree;
+        self.update_areas();
+        self.build_kdtree();
+        self.build_curved();
+    }
+
+    fn update_areas(&mut self) {
+        let mut sum = 0.0;
+        self.areas = self
+            .triangles
+            .objects
+            .iter()
+            .map(|t| {
+                sum += t.get_area();
+                sum
+            })
+            .collect();
+    }
+
+    /// Area of the flat triangles.
+    pub fn get_area(&self) -> Real {
+        self.areas.last().cloned().unwrap_or(0.0)
+    }
+
+    /// Covers everything the tree of the flat triangles depends on.
+    fn get_content_hash(&self, indices: &[[usize; 3]]) -> u64 {
+        let mut h = ContentHash::new();
+        h.write_u64(LEAF_SIZE as u64);
+        h.write_u64(self.data.vertices.len() as u64);
+        for v in &self.data.vertices {
+            h.write_vec3(&v.ps);
+        }
+        h.write_u64(indices.len() as u64);
+        for inds in indices {
+            for i in inds {
+                h.write_u64(*i as u64);
+            }
+        }
+        h.finish()
+    }
+
+    fn build_kdtree(&mut self) {
+        self.kdtree = if self.use_kdtree {
+            let indices: Vec<usize> = (0..self.triangles.objects.len()).collect();
+            KDNode::build(&indices, &self.triangles.objects)
+        } else {
+            None
+        };
+    }
+
+    fn build_curved(&mut self) {
+        self.curved = self.phong_alpha.map(|alpha| {
+            let curved = self
+                .triangles
+                .objects
+                .iter()
+                .map(|t| PhongTriangle::new(t.clone(), alpha))
+                .collect();
+            let (curved, order) = Bvh::new_linear_with_order(curved, LEAF_SIZE, true);
+            self.curved_order = order;
+            curved
+        });
+    }
+
+    /// Moves the vertices of a mesh whose topology stays the same, like an
+    /// animated character's, and refits the trees to them in linear time.
+    /// Their quality drops as the triangles move apart from the ones they
+    /// were grouped with, see `get_tree_quality`. Returns false and leaves
+    /// the mesh as it was when the number of vertices is not the same.
+    pub fn set_vertices(&mut self, vertices: Vec<Vertex>) -> bool {
+        if vertices.len() != self.data.vertices.len() {
+            return false;
+        }
+        self.share(vertices, self.data.material.clone());
+        self.update_triangles();
+        self.triangles.refit();
+        self.update_areas();
+        self.build_kdtree();
+        if let (Some(curved), Some(alpha)) = (self.curved.as_mut(), self.phong_alpha) {
+            for (c, i) in curved.objects.iter_mut().zip(&self.curved_order) {
+                *c = PhongTriangle::new(self.triangles.objects[*i].clone(), alpha);
+            }
+            curved.refit();
+        }
+        true
+    }
+
+    /// Cost of the tree right after its last build divided by its cost now,
+    /// 1 for a fresh tree and lower as refitting loosens it. Rebuilding pays
+    /// off once this falls well below 1, around 0.5 for most animations.
+    pub fn get_tree_quality(&self) -> Real {
+        let cost = self.triangles.get_cost();
+        if cost <= 0.0 {
+            return 1.0;
+        }
+        self.built_cost / cost
+    }
+
+    /// Builds the trees again for the current vertices.
+    pub fn rebuild_tree(&mut self) {
+        let indices = self.get_indices();
+        self.build_triangles(&indices, None);
+    }
+
+    pub fn get_use_kdtree(&self) -> bool {
+        self.use_kdtree
+    }
+
+    /// Hits the flat triangles through a kd-tree instead of the BVH. The
+    /// kd-tree is built again whenever the vertices move, it can not be
+    /// refitted or cached.
+    pub fn set_use_kdtree(&mut self, enabled: bool) {
+        self.use_kdtree = enabled;
+        self.build_kdtree();
+    }
+
+    /// Flat triangles in the order of the BVH.
+    pub fn get_triangles(&self) -> &[Triangle] {
+        &self.triangles.objects
+    }
+
+    /// Closest hit through the kd-tree, None when the mesh does not use
+    /// one. distance, barycentric coordinates, index in `get_triangles`
+    pub fn intersect(&self, r: &Ray3, tmin: Real, tmax: Real) -> Option<(Real, Real, Real, usize)> {
+        match self.kdtree {
+            Some(ref kdtree) => KDNode::hit(kdtree, r, tmin, tmax, &self.triangles.objects),
+            None => None,
+        }
+    }
+
+    pub fn get_phong_alpha(&self) -> Option<Real> {
+        self.phong_alpha
+    }
+
+    /// Renders the triangles as Phong tessellated curved patches, which
+    /// gives coarse meshes with smooth vertex normals round silhouettes.
+    /// `alpha` in [0, 1] scales the curvature, 0.75 is the usual choice.
+    pub fn set_phong_alpha(&mut self, alpha: Option<Real>) {
+        self.phong_alpha = alpha;
+        self.build_curved();
+    }
+
+    pub fn get_vertices(&self) -> &[Vertex] {
+        &self.data.vertices
+    }
+
+    pub fn get_material(&self) -> &Arc<dyn Material> {
+        &self.data.material
+    }
+
+    /// A copy of the flat triangles moved by `transform`, for lights that
+    /// need a mesh in world space. None when `transform` is not invertible.
+    pub fn transformed(&self, transform: &Mat4x4) -> Option<Mesh> {
+        let inverse_transform = transform.inverse()?;
+        let vertices = self
+            .data
+            .vertices
+            .iter()
+            .map(|v| Vertex {
+                ps: transform.transform_point(&v.ps),
+                nr: if v.nr.is_zero() {
+                    v.nr
+                } else {
+                    inverse_transform.transform_normal(&v.nr).normalized()
+                },
+                uv: v.uv,
+            })
+            .collect();
+        let mut mesh = Mesh::new();
+        mesh.share(vertices, self.data.material.clone());
+        mesh.build_triangles(&self.get_indices(), None);
+        Some(mesh)
+    }
+
+    pub fn set_material(&mut self, material: Arc<dyn Material>) {
+        let vertices = self.data.vertices.clone();
+        self.share(vertices, material);
+        self.update_triangles();
+        self.build_curved();
+    }
+}
+
+impl Default for Mesh {
+    fn default() -> Self {
+        Mesh::new()
+    }
+}
+
+impl Hitable for Mesh {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        if let Some(ref curved) = self.curved {
+            return curved.hit(r, t_min, t_max);
+        }
+        if self.use_kdtree {
+            let (t, u, v, triangle) = self.intersect(r, t_min, t_max)?;
+            return Some(self.triangles.objects[triangle].get_hit_info(t, u, v));
+        }
+        self.triangles.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        if let Some(ref curved) = self.curved {
+            return curved.bounding_box();
+        }
+        self.triangles.bounding_box()
+    }
+
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Light>>) {
+        if self.data.material.get_emission().is_some() {
+            if let Some(m) = self.transformed(&Mat4x4::new()) {
+                lights.push(Arc::new(AreaLight::new(m)));
+            }
+        }
+    }
+}
+
+/// Points are picked by area on the flat triangles, also when the mesh
+/// renders curved ones.
+impl Shape for Mesh {
+    fn sample_from(&self, rec: &HitInfo, u: &Vec2) -> Option<(HitInfo<'_>, Real)> {
+        let area = self.get_area();
+        if area <= 0.0 {
+            return None;
+        }
+        let (i, u) = sampling::pick_by_sum(&self.areas, u);
+        let info = self.triangles.objects[i].sample_point(&u);
+        let pdf = area_to_solid_angle(&rec.p, &info.p, &info.ng, 1.0 / area);
+        Some((info, pdf))
+    }
+
+    /// A direction can cross a mesh many times, every crossing is a point
+    /// `sample_from` may have picked.
+    fn pdf_from(&self, rec: &HitInfo, wi: &Vec3) -> Real {
+        let area = self.get_area();
+        if area <= 0.0 {
+            return 0.0;
+        }
+        let r = rec.spawn_ray(wi);
+        let mut t_min = r.t_min;
+        let mut last = r.t_min;
+        let mut pdf = 0.0;
+        // The rounded distance of a crossing can fall back below the start
+        // of the search, so it only moves forward and each is counted once.
+        while let Some(hit) = self.triangles.hit(&r, t_min, r.t_max) {
+            if hit.t > last {
+                pdf += area_to_solid_angle(&rec.p, &hit.p, &hit.ng, 1.0 / area);
+                last = hit.t;
+            }
+            t_min = next_up(hit.t.max(t_min));
+        }
+        pdf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::geometry::BasicGeometry;
+    use super::super::transformed::Transformed;
+    use super::*;
+
+    /// Uniform values in [0, 1) from a fixed xorshift stream.
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> Real {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 11) as Real / (1u64 << 53) as Real
+        }
+
+        fn point(&mut self, scale: Real) -> Vec3 {
+            Vec3 {
+                x: (self.next() - 0.5) * scale,
+                y: (self.next() - 0.5) * scale,
+                z: (self.next() - 0.5) * scale,
+            }
+        }
+    }
+
+    /// Small triangles scattered in a cube of side 4 around the origin.
+    fn soup(random: &mut Random, count: usize) -> Mesh {
+        let mut vertices = Vec::new();
+        for _ in 0..count {
+            let corner = random.point(4.0);
+            for i in 0..3 {
+                let mut v = Vertex::new();
+                v.ps = if i == 0 {
+                    corner
+                } else {
+                    &corner + &random.point(0.6)
+                };
+                vertices.push(v);
+            }
+        }
+        let indices: Vec<[usize; 3]> = (0..count).map(|i| [3 * i, 3 * i + 1, 3 * i + 2]).collect();
+        let mut mesh = Mesh::new();
+        mesh.set_data(vertices, &indices, None);
+        mesh
+    }
+
+    #[test]
+    fn kdtree_finds_the_same_nearest_hits_as_the_bvh() {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        let mut mesh = soup(&mut random, 300);
+        let rays: Vec<Ray3> = (0..500)
+            .map(|_| {
+                let o = random.point(12.0);
+                Ray3::new(o, &random.point(2.0) - &o)
+            })
+            .collect();
+        let expected: Vec<Option<Real>> = rays
+            .iter()
+            .map(|r| mesh.hit(r, 0.0, Real::MAX).map(|h| h.t))
+            .collect();
+        mesh.set_use_kdtree(true);
+        let mut hits = 0;
+        for (r, expected) in rays.iter().zip(&expected) {
+            assert_eq!(mesh.hit(r, 0.0, Real::MAX).map(|h| h.t), *expected);
+            hits += expected.is_some() as usize;
+        }
+        assert!(hits > 50);
+    }
+
+    fn encloses(outer: &AABBox3, inner: &AABBox3) -> bool {
+        outer.blf.x <= inner.blf.x
+            && outer.blf.y <= inner.blf.y
+            && outer.blf.z <= inner.blf.z
+            && outer.trr.x >= inner.trr.x
+            && outer.trr.y >= inner.trr.y
+            && outer.trr.z >= inner.trr.z
+    }
+
+    /// The vertices of `mesh` with every one moved by `offset` of its
+    /// index.
+    fn moved(mesh: &Mesh, offset: &mut dyn FnMut(usize) -> Vec3) -> Vec<Vertex> {
+        mesh.get_vertices()
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let mut v = *v;
+                v.ps = &v.ps + &offset(i);
+                v
+            })
+            .collect()
+    }
+
+    #[test]
+    fn set_vertices_keeps_the_mesh_when_the_count_differs() {
+        let mut random = Random(0x9e37_79b9_7f4a_7c15);
+        let mut mesh = soup(&mut random, 20);
+        let area = mesh.get_area();
+        let mut vertices = moved(&mesh, &mut |_| Vec3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        });
+        vertices.pop();
+        assert!(!mesh.set_vertices(vertices));
+        assert_eq!(mesh.get_vertices().len(), 60);
+        assert_eq!(mesh.get_area(), area);
+        assert_eq!(mesh.get_tree_quality(), 1.0);
+    }
+
+    #[test]
+    fn refitted_bounds_enclose_the_moved_triangles() {
+        let mut random = Random(0x6a09_e667_f3bc_c908);
+        let mut mesh = soup(&mut random, 200);
+        let vertices = moved(&mesh, &mut |_| random.point(1.0));
+        assert!(mesh.set_vertices(vertices));
+        let tree = &mesh.triangles;
+        for (i, node) in tree.nodes.iter().enumerate() {
+            if node.is_leaf() {
+                for t in &tree.objects[node.offset..node.offset + node.count] {
+                    assert!(encloses(&node.bbox, &t.get_aabb()));
+                }
+            } else {
+                assert!(encloses(&node.bbox, &tree.nodes[i + 1].bbox));
+                assert!(encloses(&node.bbox, &tree.nodes[node.offset].bbox));
+            }
+        }
+        for t in &tree.objects {
+            for (p, i) in t.get_positions().iter().zip(t.get_indices().iter()) {
+                assert!((p - &mesh.get_vertices()[*i].ps).is_zero());
+            }
+        }
+    }
+
+    #[test]
+    fn tree_quality_drops_after_a_large_deformation() {
+        let mut random = Random(0xbb67_ae85_84ca_a73b);
+        let mut mesh = soup(&mut random, 500);
+        assert_eq!(mesh.get_tree_quality(), 1.0);
+        let vertices = moved(&mesh, &mut |_| random.point(0.01));
+        assert!(mesh.set_vertices(vertices));
+        let small = mesh.get_tree_quality();
+        assert!(small > 0.9, "{}", small);
+        // Every triangle jumps to another place of the cube, which leaves
+        // the old groups spread over all of it.
+        let mut jump = Vec3::new();
+        let vertices = moved(&mesh, &mut |i| {
+            if i % 3 == 0 {
+                jump = random.point(4.0);
+            }
+            jump
+        });
+        assert!(mesh.set_vertices(vertices));
+        let large = mesh.get_tree_quality();
+        assert!(large < 0.75, "{}", large);
+        mesh.rebuild_tree();
+        assert_eq!(mesh.get_tree_quality(), 1.0);
+    }
+
+    #[test]
+    fn triangles_share_the_vertices_and_material_of_the_mesh() {
+        let mut random = Random(0x3c6e_f372_fe94_f82b);
+        let mut mesh = soup(&mut random, 30);
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3 {
+            x: 0.1,
+            y: 0.2,
+            z: 0.3,
+        }));
+        mesh.set_material(material.clone());
+        mesh.set_phong_alpha(Some(0.75));
+        let vertices = moved(&mesh, &mut |_| random.point(0.1));
+        assert!(mesh.set_vertices(vertices));
+        assert!(Arc::ptr_eq(mesh.get_material(), &material));
+        for t in mesh.get_triangles() {
+            assert!(Arc::ptr_eq(t.get_material(), &material));
+            for (p, i) in t.get_positions().iter().zip(t.get_indices().iter()) {
+                assert!((p - &mesh.get_vertices()[*i].ps).is_zero());
+            }
+        }
+        for c in &mesh.curved.as_ref().unwrap().objects {
+            assert!(Arc::ptr_eq(c.get_triangle().get_material(), &material));
+        }
+    }
+
+    #[test]
+    fn flattening_transforms_are_refused() {
+        let mut random = Random(0xbb67_ae85_84ca_a73b);
+        let mesh = soup(&mut random, 8);
+        let flatten = Mat4x4::scale(&Vec3 {
+            x: 1.0,
+            y: 0.0,
+            z: 1.0,
+        });
+        assert!(mesh.transformed(&flatten).is_none());
+        assert!(mesh.transformed(&Mat4x4::new()).is_some());
+
+        let mut g = BasicGeometry::new();
+        let place = Mat4x4::translation(&Vec3 {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        });
+        assert!(g.set_transform(place));
+        assert!(!g.set_transform(flatten));
+        assert_eq!(g.get_transform().data, place.data);
+        assert!(g.instance(flatten).is_none());
+
+        let mesh = Arc::new(mesh);
+        assert!(Transformed::new(mesh.clone(), flatten).is_none());
+        assert!(Transformed::moving(mesh.clone(), flatten, place, 0.0, 1.0).is_none());
+        let mut t = Transformed::new(mesh, place).unwrap();
+        assert!(!t.set_transform(flatten));
+        assert_eq!(t.get_transform().data, place.data);
     }
 }
@@ -1,6 +1,17 @@
-// pub mod camera;
-// pub mod engine;
+pub mod camera;
+pub mod engine;
+pub mod environment;
+pub mod geometry;
 pub mod hit;
-// pub mod kernel;
+pub mod hitable_list;
+pub mod kernel;
+pub mod light;
 pub mod material;
-// pub mod vertex;
+pub mod mesh;
+pub mod renderer;
+pub mod sampler;
+pub mod scene;
+pub mod scenes_manager;
+pub mod texture;
+pub mod transformed;
+pub mod vertex;
@@ -1,51 +1,330 @@
-use ::math::vector::{
-    Vec3,
-};
-use ::render::scene::Scene;
+use std::sync::Arc;
+
+use super::super::math::onb::Onb;
+use super::super::math::ray::Ray3;
+use super::super::math::sampling;
+use super::super::math::vector::{Vec2, Vec3};
+use super::super::math::Real;
+use super::environment::Environment;
+use super::hit::{Hitable, Info as HitInfo};
+use super::light::{Light, LightSample};
+use super::sampler::Sampler;
+
+#[derive(Debug, Clone)]
+pub enum Background {
+    Solid(Vec3),
+    /// Blends from `horizon` to `zenith` by the ray direction projected on `up`.
+    Sky {
+        up: Vec3,
+        horizon: Vec3,
+        zenith: Vec3,
+    },
+    /// An HDR image around the scene, sampled by its luminance.
+    Environment(Arc<Environment>),
+}
+
+impl Background {
+    /// Radiance arriving from the unit direction `d`.
+    pub fn radiance(&self, d: &Vec3) -> Vec3 {
+        match self {
+            Background::Solid(c) => *c,
+            Background::Sky {
+                up,
+                horizon,
+                zenith,
+            } => {
+                let t = 0.5 * (d.dot(up) + 1.0);
+                &(horizon * (1.0 - t)) + &(zenith * t)
+            }
+            Background::Environment(environment) => environment.radiance(d),
+        }
+    }
+}
+
+/// The background surrounds the scene and lights it like any other light.
+/// The solid and sky ones change slowly over directions, so they are picked
+/// by the cosine to the shading normal, which never wastes a sample behind
+/// the surface. Environments pick them by their own luminance.
+impl Light for Background {
+    fn sample_li(&self, rec: &HitInfo, u: &Vec2) -> Option<LightSample> {
+        let (wi, pdf) = match self {
+            Background::Environment(environment) => environment.sample(u)?,
+            _ => {
+                let wi = Onb::from_w(&rec.n).local_to_world(&sampling::cosine_hemisphere(u));
+                (wi, sampling::cosine_hemisphere_pdf(wi.dot(&rec.n)))
+            }
+        };
+        Some(LightSample {
+            wi,
+            li: self.radiance(&wi),
+            pdf,
+            shadow: rec.spawn_ray(&wi),
+        })
+    }
+
+    fn pdf_li(&self, rec: &HitInfo, wi: &Vec3) -> Real {
+        match self {
+            Background::Environment(environment) => environment.pdf(wi),
+            _ => sampling::cosine_hemisphere_pdf(wi.dot(&rec.n)),
+        }
+    }
+}
 
 pub struct Renderer {
-    samples_count_sqrt: f64,
-    bounce_count: u64,
+    pub bounce_count: u32,
+    pub background: Background,
+    /// Sampled at every hit along with the emitters and the background.
+    pub lights: Vec<Arc<dyn Light>>,
+    /// The glowing surfaces of the world, set by `add_emitters`.
+    pub emitters: Vec<Arc<dyn Light>>,
 }
 
+impl Renderer {
+    pub fn new(bounce_count: u32, background: Background) -> Self {
+        Self {
+            bounce_count,
+            background,
+            lights: Vec::new(),
+            emitters: Vec::new(),
+        }
+    }
+
+    pub fn add_light(&mut self, light: Arc<dyn Light>) {
+        self.lights.push(light);
+    }
+
+    /// Makes a light of every glowing surface of `world` that can be
+    /// sampled, in place of those of an earlier call. The engine does it for
+    /// the world it renders.
+    pub fn add_emitters(&mut self, world: &dyn Hitable) {
+        self.emitters.clear();
+        world.collect_emitters(&mut self.emitters);
+    }
 
-const BOUNCE_COUNT: u64 = 2;
-
-pub fn render_scene(samples_count_sqrt: &u32, scene: &scene, screen_width: &u32, screen_height: &u32, bounce_count: u64) -> [Vec3<f64>] {
-    let mut screen = [Vec3<f64>; screen_width * screen_height];
-    screen[0] = Vec3::new();
-    let camera = scene.cameras[scene.active_camera_index];
-    {
-        let mut screen_index = 0u32;
-        let mut pixel_sample_count = 0u32;
-        let x_step = 1.0f64 / (samples_count_sqrt * screen_width);
-        let y_step = 1.0f64 / (samples_count_sqrt * screen_height);
-        let mut y = -1.0;
-        loop {
-            if y > 1.0 {
-                break;
+    /// The emitters come after the added lights and the background last.
+    fn get_light(&self, index: usize) -> &dyn Light {
+        let light = match index.checked_sub(self.lights.len()) {
+            None => self.lights.get(index),
+            Some(i) => self.emitters.get(i),
+        };
+        match light {
+            Some(light) => light.as_ref(),
+            None => &self.background,
+        }
+    }
+
+    fn get_lights_count(&self) -> usize {
+        self.lights.len() + self.emitters.len() + 1
+    }
+
+    /// Density per steradian with which `sample_light` picks the unit
+    /// direction `wi` from `rec`, over all the lights that are not delta.
+    fn get_light_pdf(&self, rec: &HitInfo, wi: &Vec3) -> Real {
+        let count = self.get_lights_count();
+        let pdf: Real = (0..count)
+            .map(|i| self.get_light(i))
+            .filter(|l| !l.is_delta())
+            .map(|l| l.pdf_li(rec, wi))
+            .sum();
+        pdf / count as Real
+    }
+
+    /// Radiance arriving along the unit direction `d` of a ray that hits
+    /// nothing.
+    fn get_escaped_radiance(&self, d: &Vec3) -> Vec3 {
+        let mut result = self.background.radiance(d);
+        for l in self.lights.iter().chain(&self.emitters) {
+            result += &l.le(d);
+        }
+        result
+    }
+
+    /// Light leaving `rec` toward `wo` that arrives straight from one light
+    /// picked at random. Delta lights are only checked for a blocker, for
+    /// the others whatever the direction reaches gives the light, and it is
+    /// weighted against the material finding the same light. Their density
+    /// is the one of picking the direction through any light, so a light
+    /// hiding another one, or the background, is still counted once.
+    fn sample_light(
+        &self,
+        rec: &HitInfo,
+        wo: &Vec3,
+        time: Real,
+        world: &dyn Hitable,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        let count = self.get_lights_count();
+        let index = ((sampler.get_1d() * count as Real) as usize).min(count - 1);
+        let light = self.get_light(index);
+        let s = match light.sample_li(rec, &sampler.get_2d()) {
+            Some(s) if s.pdf > 0.0 => s,
+            _ => return Vec3::new(),
+        };
+        let f = &rec.m.eval(rec, wo, &s.wi) * s.wi.dot(&rec.n).abs();
+        if f.is_zero() {
+            return Vec3::new();
+        }
+        if light.is_delta() {
+            let shadow = s.shadow.with_time(time);
+            if world.hit(&shadow, shadow.t_min, shadow.t_max).is_some() {
+                return Vec3::new();
             }
-            let mut x = -1.0;
-            loop {
-                if x > 1.0 {
-                    break;
-                }
-                let ray = camera.get_ray(x, y);
-                screen[screen_index] += bouncer(scene, &r);
-                if pixel_sample_count > *samples_count_sqrt {
-                    pixel_sample_count = 0;
-                    screen_index++;
-                    screen[screen_index] = Vec3::new();
+            return &(&f * &s.li) * (count as Real / s.pdf);
+        }
+        let r = rec.spawn_ray(&s.wi).with_time(time);
+        let li = match world.hit(&r, r.t_min, r.t_max) {
+            Some(hit) => hit.m.emitted(&hit, &-&s.wi),
+            None => self.get_escaped_radiance(&s.wi),
+        };
+        if li.is_zero() {
+            return Vec3::new();
+        }
+        let pdf = self.get_light_pdf(rec, &s.wi);
+        if pdf <= 0.0 {
+            return Vec3::new();
+        }
+        let weight = sampling::power_heuristic(pdf, rec.m.pdf(rec, wo, &s.wi));
+        &(&f * &li) * (weight / pdf)
+    }
+
+    /// Weight of light found by following the material from `previous`
+    /// along the unit direction `wi`, against finding it by sampling lights.
+    fn get_scatter_weight(&self, previous: &Option<HitInfo>, scatter_pdf: Real, wi: &Vec3) -> Real {
+        match *previous {
+            Some(ref p) if scatter_pdf > 0.0 => {
+                sampling::power_heuristic(scatter_pdf, self.get_light_pdf(p, wi))
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// Follows `r` through `world` until it escapes to the background, gets
+    /// absorbed or runs out of bounces. At every hit one light is sampled
+    /// directly, and light found by the scattered rays is weighted by the
+    /// power heuristic so the two estimates add up without counting any
+    /// light twice.
+    pub fn radiance(&self, r: &Ray3, world: &dyn Hitable, sampler: &mut dyn Sampler) -> Vec3 {
+        let mut ray = *r;
+        let mut throughput = Vec3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        };
+        let mut result = Vec3::new();
+        // Density the material chose `ray` with, zero for camera rays and
+        // single direction lobes whose light only they can find.
+        let mut scatter_pdf = 0.0;
+        let mut previous: Option<HitInfo> = None;
+        for _ in 0..self.bounce_count {
+            let d = ray.d.normalized();
+            let rec = match world.hit(&ray, ray.t_min, ray.t_max) {
+                Some(rec) => rec,
+                None => {
+                    let li = self.get_escaped_radiance(&d);
+                    let weight = self.get_scatter_weight(&previous, scatter_pdf, &d);
+                    result += &(&(&throughput * &li) * weight);
+                    return result;
                 }
-                pixel_sample_count++;
-                x += x_step;
+            };
+            let wo = -&d;
+            let emitted = rec.m.emitted(&rec, &wo);
+            if !emitted.is_zero() {
+                let weight = self.get_scatter_weight(&previous, scatter_pdf, &d);
+                result += &(&(&throughput * &emitted) * weight);
             }
-            y += y_step;
+            result += &(&throughput * &self.sample_light(&rec, &wo, ray.time, world, sampler));
+            let uc = sampler.get_1d();
+            let s = match rec.m.sample(&rec, &wo, uc, &sampler.get_2d()) {
+                Some(s) => s,
+                None => return result,
+            };
+            throughput *= &s.weight;
+            scatter_pdf = if s.lobe.is_specular() { 0.0 } else { s.pdf };
+            ray = rec.spawn_ray(&s.wi).with_time(ray.time);
+            previous = Some(rec);
         }
+        result
     }
-    screen
 }
 
-fn bouncer<E>(scene: &scene, ray: &Ray3<E>, bounce_count: u64) -> Vec3<E> where E: VectorElement {
-    scene.
+#[cfg(test)]
+mod tests {
+    use super::super::super::math::matrix::Mat4x4;
+    use super::super::super::math::plane::Plane;
+    use super::super::super::math::quad::Quad;
+    use super::super::hitable_list::HitableList;
+    use super::super::material::{DiffuseLight, Lambertian};
+    use super::super::sampler::IndependentSampler;
+    use super::super::transformed::Transformed;
+    use super::*;
+
+    fn v(x: Real, y: Real, z: Real) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    /// A floor with half of the light reflected, `light` is added above it.
+    fn floor_with(light: Box<dyn Hitable + Sync + Send>) -> HitableList {
+        let mut world = HitableList::new();
+        world.add(Box::new(Plane::new(
+            v(0.0, 0.0, 0.0),
+            v(0.0, 1.0, 0.0),
+            Box::new(Lambertian::new(v(0.5, 0.5, 0.5))),
+        )));
+        world.add(light);
+        world
+    }
+
+    /// Mean radiance reaching a point above the floor from the floor, with
+    /// the glowing surfaces of `world` sampled as lights or only hit.
+    fn floor_radiance(world: &HitableList, register_lights: bool, n: u32) -> Real {
+        let mut renderer = Renderer::new(4, Background::Solid(Vec3::new()));
+        if register_lights {
+            renderer.add_emitters(world);
+            // The engine's call already made them, another one replaces them.
+            renderer.add_emitters(world);
+            assert_eq!(renderer.emitters.len(), 1);
+        }
+        let r = Ray3::new(v(0.0, 0.5, 0.0), v(0.3, -1.0, 0.2));
+        let mut sampler = IndependentSampler::new(1);
+        let sum: Real = (0..n)
+            .map(|i| {
+                sampler.start_pixel_sample((0, 0), i);
+                renderer.radiance(&r, world, &mut sampler).y
+            })
+            .sum();
+        sum / n as Real
+    }
+
+    /// A ceiling light that covers nearly the whole sky of the floor, which
+    /// then gives back its albedo times the light's radiance.
+    #[test]
+    fn black_background_keeps_its_share_of_light_sampling() {
+        let world = floor_with(Box::new(Quad::new(
+            v(-1000.0, 1.0, -1000.0),
+            v(2000.0, 0.0, 0.0),
+            v(0.0, 0.0, 2000.0),
+            Box::new(DiffuseLight::new(v(1.0, 1.0, 1.0))),
+        )));
+        assert!((floor_radiance(&world, true, 4000) - 0.5).abs() < 0.02);
+        assert!((floor_radiance(&world, false, 4000) - 0.5).abs() < 0.02);
+    }
+
+    /// An unevenly scaled and tilted light sampled through its transform
+    /// agrees with the one only found by following the floor's material.
+    #[test]
+    fn transformed_emitters_are_sampled_in_world_space() {
+        let quad = Quad::new(
+            v(-0.5, 0.0, -0.5),
+            v(1.0, 0.0, 0.0),
+            v(0.0, 0.0, 1.0),
+            Box::new(DiffuseLight::new(v(1.0, 1.0, 1.0))),
+        );
+        let transform = Mat4x4::translation(&v(0.5, 1.0, 0.0))
+            * Mat4x4::rotation_transform(0.4, &v(1.0, 0.0, 0.0))
+            * Mat4x4::scale(&v(3.0, 1.0, 0.5));
+        let world = floor_with(Box::new(Transformed::new(quad, transform).unwrap()));
+        let sampled = floor_radiance(&world, true, 20000);
+        let hit = floor_radiance(&world, false, 20000);
+        assert!((sampled - hit).abs() < 0.01, "{} {}", sampled, hit);
+    }
 }
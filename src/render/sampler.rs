@@ -0,0 +1,486 @@
+use super::super::math::vector::Vec2;
+use super::super::math::Real;
+
+/// Largest value below 1, samples are clamped to it so they stay in [0, 1).
+const ONE_MINUS_EPSILON: Real = 1.0 - Real::EPSILON * 0.5;
+
+/// Bases of the Halton dimensions, later dimensions are filled randomly.
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Source of the sample values a pixel sample consumes, one dimension at a
+/// time. The film position, shutter time and every scattering decision each
+/// take their own dimensions, in the same order for every sample, so the
+/// dimensions of a sample are well distributed against each other.
+///
+/// Values depend only on the seed, the pixel, the sample index and the
+/// dimension, never on which thread asks or in what order, so a render is
+/// reproduced bit for bit by the same seed.
+pub trait Sampler: Sync + Send {
+    fn get_samples_per_pixel(&self) -> u32;
+    /// Restarts the dimensions for the `index`th sample of `pixel`.
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32);
+    fn get_1d(&mut self) -> Real;
+    fn get_2d(&mut self) -> Vec2;
+    /// A fresh sampler with the same settings whose values are derived from
+    /// `seed`, one per rendering thread.
+    fn clone_seeded(&self, seed: u64) -> Box<dyn Sampler>;
+}
+
+/// Finalizer of SplitMix64, spreads every input bit over the whole output.
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+/// Every random stream is keyed by the hash of the integers that tell it
+/// apart, each step is a bijection so keys of the same length rarely meet.
+fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0x9e37_79b9_7f4a_7c15, |h, &v| mix_bits(h ^ v))
+}
+
+fn to_unit(bits: u32) -> Real {
+    (bits as Real * (1.0 / 4_294_967_296.0)).min(ONE_MINUS_EPSILON)
+}
+
+fn hash_to_unit(h: u64) -> Real {
+    to_unit((h >> 32) as u32)
+}
+
+/// Element `i` of a pseudo random permutation of [0, `l`) picked by `p`,
+/// without storing it (Kensler, Correlated Multi-Jittered Sampling 2013).
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            return i.wrapping_add(p) % l;
+        }
+    }
+}
+
+/// Owen scrambling of the binary digits of `x`, most significant first,
+/// with a hash standing in for the tree of random flips (Burley, Practical
+/// Hash-based Owen Scrambling 2020).
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+/// First two dimensions of the Sobol sequence as 32 bit fractions, the
+/// first is the van der Corput sequence and the second has the generator
+/// v[k] = v[k - 1] ^ (v[k - 1] >> 1).
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let mut x = 0;
+    let mut y = 0;
+    let mut v = 1u32 << 31;
+    let mut i = index;
+    let mut bit = 0;
+    while i != 0 {
+        if i & 1 != 0 {
+            x ^= 1u32 << (31 - bit);
+            y ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+        bit += 1;
+    }
+    (x, y)
+}
+
+fn radical_inverse(base: u32, mut index: u32) -> Real {
+    let inv_base = 1.0 / base as Real;
+    let mut inv_base_n = 1.0;
+    let mut reversed: Real = 0.0;
+    while index != 0 {
+        let next = index / base;
+        let digit = index - next * base;
+        reversed = reversed * base as Real + digit as Real;
+        inv_base_n *= inv_base;
+        index = next;
+    }
+    (reversed * inv_base_n).min(ONE_MINUS_EPSILON)
+}
+
+/// Uncorrelated uniform values, the baseline the others are measured by.
+#[derive(Clone)]
+pub struct IndependentSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+}
+
+impl IndependentSampler {
+    pub fn new(samples_per_pixel: u32) -> Self {
+        Self {
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed: 0,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn next(&mut self) -> Real {
+        let u = hash_to_unit(hash(&[
+            self.seed,
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.index as u64,
+            self.dimension as u64,
+        ]));
+        self.dimension += 1;
+        u
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn get_samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> Real {
+        self.next()
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        Vec2 {
+            x: self.next(),
+            y: self.next(),
+        }
+    }
+
+    fn clone_seeded(&self, seed: u64) -> Box<dyn Sampler> {
+        let mut s = self.clone();
+        s.seed = seed;
+        Box::new(s)
+    }
+}
+
+/// Splits every dimension in as many strata as there are samples and puts
+/// one sample in each, at a random place inside it when `jitter` is set.
+/// The strata are shuffled per dimension so dimensions do not correlate.
+#[derive(Clone)]
+pub struct StratifiedSampler {
+    x_samples: u32,
+    y_samples: u32,
+    jitter: bool,
+    seed: u64,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+}
+
+impl StratifiedSampler {
+    /// 2D dimensions get an `x_samples` by `y_samples` grid.
+    pub fn new(x_samples: u32, y_samples: u32, jitter: bool) -> Self {
+        Self {
+            x_samples: x_samples.max(1),
+            y_samples: y_samples.max(1),
+            jitter,
+            seed: 0,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// Stratum of the current sample in a dimension and a random offset
+    /// inside it.
+    fn next(&mut self) -> (u32, Real, Real) {
+        let h = hash(&[
+            self.seed,
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension as u64,
+        ]);
+        self.dimension += 1;
+        let count = self.x_samples * self.y_samples;
+        let stratum = permutation_element(self.index % count, count, h as u32);
+        let offset_hash = mix_bits(h ^ self.index as u64);
+        let (dx, dy) = if self.jitter {
+            (hash_to_unit(offset_hash), to_unit(offset_hash as u32))
+        } else {
+            (0.5, 0.5)
+        };
+        (stratum, dx, dy)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn get_samples_per_pixel(&self) -> u32 {
+        self.x_samples * self.y_samples
+    }
+
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> Real {
+        let count = self.get_samples_per_pixel();
+        let (stratum, dx, _) = self.next();
+        ((stratum as Real + dx) / count as Real).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let (stratum, dx, dy) = self.next();
+        let x = stratum % self.x_samples;
+        let y = stratum / self.x_samples;
+        Vec2 {
+            x: ((x as Real + dx) / self.x_samples as Real).min(ONE_MINUS_EPSILON),
+            y: ((y as Real + dy) / self.y_samples as Real).min(ONE_MINUS_EPSILON),
+        }
+    }
+
+    fn clone_seeded(&self, seed: u64) -> Box<dyn Sampler> {
+        let mut s = self.clone();
+        s.seed = seed;
+        Box::new(s)
+    }
+}
+
+/// The Halton sequence, a prime base per dimension, shifted per pixel by a
+/// random toroidal rotation so neighbouring pixels do not repeat each other.
+#[derive(Clone)]
+pub struct HaltonSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+}
+
+impl HaltonSampler {
+    pub fn new(samples_per_pixel: u32) -> Self {
+        Self {
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed: 0,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn next(&mut self) -> Real {
+        let h = hash(&[
+            self.seed,
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension as u64,
+        ]);
+        let u = match PRIMES.get(self.dimension as usize) {
+            Some(&base) => {
+                let u = radical_inverse(base, self.index) + hash_to_unit(h);
+                if u >= 1.0 {
+                    u - 1.0
+                } else {
+                    u
+                }
+            }
+            None => hash_to_unit(mix_bits(h ^ self.index as u64)),
+        };
+        self.dimension += 1;
+        u.min(ONE_MINUS_EPSILON)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn get_samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> Real {
+        self.next()
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        Vec2 {
+            x: self.next(),
+            y: self.next(),
+        }
+    }
+
+    fn clone_seeded(&self, seed: u64) -> Box<dyn Sampler> {
+        let mut s = self.clone();
+        s.seed = seed;
+        Box::new(s)
+    }
+}
+
+/// Owen scrambled Sobol points: every 1D or 2D request reads the first
+/// Sobol dimensions of a per pixel and per dimension shuffled index, with
+/// its own scrambling. Best with a power of two samples per pixel.
+#[derive(Clone)]
+pub struct SobolSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+}
+
+impl SobolSampler {
+    pub fn new(samples_per_pixel: u32) -> Self {
+        Self {
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed: 0,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// Scrambled Sobol point of the current dimension, with the hash it
+    /// used so the second coordinate can be scrambled independently.
+    fn next(&mut self) -> ((u32, u32), u64) {
+        let h = hash(&[
+            self.seed,
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension as u64,
+        ]);
+        self.dimension += 1;
+        let index = owen_scramble(self.index, h as u32);
+        (sobol_2d(index), h)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn get_samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> Real {
+        let ((x, _), h) = self.next();
+        to_unit(owen_scramble(x, (h >> 32) as u32))
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let ((x, y), h) = self.next();
+        let h2 = mix_bits(h);
+        Vec2 {
+            x: to_unit(owen_scramble(x, (h >> 32) as u32)),
+            y: to_unit(owen_scramble(y, (h2 >> 32) as u32)),
+        }
+    }
+
+    fn clone_seeded(&self, seed: u64) -> Box<dyn Sampler> {
+        let mut s = self.clone();
+        s.seed = seed;
+        Box::new(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Values of a few dimensions of the `index`th sample of `pixel`.
+    fn draw(sampler: &mut dyn Sampler, pixel: (u32, u32), index: u32) -> Vec<Real> {
+        sampler.start_pixel_sample(pixel, index);
+        let mut values = Vec::new();
+        for _ in 0..20 {
+            values.push(sampler.get_1d());
+            let u = sampler.get_2d();
+            values.push(u.x);
+            values.push(u.y);
+        }
+        values
+    }
+
+    fn samplers() -> Vec<Box<dyn Sampler>> {
+        vec![
+            Box::new(IndependentSampler::new(16)),
+            Box::new(StratifiedSampler::new(4, 4, true)),
+            Box::new(HaltonSampler::new(16)),
+            Box::new(SobolSampler::new(16)),
+        ]
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_values_in_any_order() {
+        let samples: Vec<((u32, u32), u32)> = [(0, 0), (3, 7), (640, 479)]
+            .iter()
+            .flat_map(|p| (0..16).map(move |i| (*p, i)))
+            .collect();
+        for sampler in samplers() {
+            let mut a = sampler.clone_seeded(42);
+            let mut b = sampler.clone_seeded(42);
+            let expected: Vec<Vec<Real>> = samples
+                .iter()
+                .map(|&(p, i)| draw(a.as_mut(), p, i))
+                .collect();
+            for (k, &(p, i)) in samples.iter().enumerate().rev() {
+                let values = draw(b.as_mut(), p, i);
+                assert_eq!(values, expected[k]);
+                assert!(values.iter().all(|v| (0.0..1.0).contains(v)));
+            }
+        }
+    }
+
+    #[test]
+    fn other_seeds_give_other_values() {
+        for sampler in samplers() {
+            let mut a = sampler.clone_seeded(1);
+            let mut b = sampler.clone_seeded(2);
+            assert_ne!(draw(a.as_mut(), (5, 5), 3), draw(b.as_mut(), (5, 5), 3));
+        }
+    }
+}
@@ -1,24 +1,57 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
 
-use ::io::file::Stream;
-use ::math::ray::Ray3;
-use ::math::vector::{
-    Vec2,
-    Vec3,
-};
-use ::math::triangle::Triangle;
-use ::render::camera::{
-    Camera,
-    camera_reader,
-};
-use ::render::geometry::BasicGeometry;
+use super::super::io::cache::Cache;
+use super::super::io::file::Stream;
+use super::super::math::aabbox::AABBox3;
+use super::super::math::bvh::Bvh;
+use super::super::math::ray::Ray3;
+use super::super::math::Real;
+use super::camera::Camera;
+use super::geometry::BasicGeometry;
+use super::hit::{Hitable, Info as HitInfo};
+use super::light::Light;
+
+/// Geometry instances per leaf of the top level tree.
+const GEOMETRIES_LEAF_SIZE: usize = 2;
+
+/// Why `Scene::read` stopped importing a scene.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadError {
+    /// An object whose record layout is not known, like cameras and suns.
+    /// Reading past it would misread every record after it.
+    UnsupportedObject { object_type: u8, name: String },
+    /// Copy geometries are not supported yet.
+    UnsupportedCopyGeometries(u16),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::UnsupportedObject { object_type, name } => write!(
+                f,
+                "Object {:?} has the unsupported type {}.",
+                name, object_type
+            ),
+            ReadError::UnsupportedCopyGeometries(count) => {
+                write!(f, "{} copy geometries are not supported yet.", count)
+            }
+        }
+    }
+}
 
 pub struct Scene {
-    pub gs: Vec<BasicGeometry>,
+    pub gs: Vec<Arc<BasicGeometry>>,
     pub gm: HashMap<String, usize>,
-    // pub gt: TODO KDTree for geometries in scene
-    pub cameras: Vec<Box<Camera>>,
+    /// Top level tree over the geometry instances, every instance holds the
+    /// bottom level trees of its meshes.
+    pub gt: Bvh<Arc<BasicGeometry>>,
+    pub cameras: Vec<Box<dyn Camera>>,
     pub active_camera_index: usize,
+    /// Lights added to the scene, `collect_emitters` hands them to the
+    /// renderer along with the glowing geometry.
+    pub lights: Vec<Arc<dyn Light>>,
 }
 
 impl Scene {
@@ -26,12 +59,17 @@ impl Scene {
         Scene {
             gs: Vec::new(),
             gm: HashMap::new(),
+            gt: Bvh::new(Vec::new(), GEOMETRIES_LEAF_SIZE),
             cameras: Vec::new(),
             active_camera_index: 0,
+            lights: Vec::new(),
         }
     }
 
-    pub fn read(&mut self, s: &mut Stream) {
+    /// Mesh trees are loaded from and saved to `cache` when there is one.
+    /// Stops at the first object it can not read, the scene then holds the
+    /// objects before it.
+    pub fn read(&mut self, s: &mut Stream, cache: Option<&Cache>) -> Result<(), ReadError> {
         let objects_count = s.read(&0u16);
         for _ in 0..objects_count {
             s.read(&0u8);
@@ -44,35 +82,146 @@ impl Scene {
             match object_type {
                 1 => {
                     let mut g = BasicGeometry::new();
-                    g.read(s);
-                    self.gm.insert(name, self.gs.len());
-                    self.gs.push(g);
-                }
-                4 => {
-                    self.active_camera_index = self.cameras.len();
-                    self.cameras.push(camera_reader(s));
-                }
-                5 => {
-                    panic!("Sun are not supported yet.");
+                    // Instances that can not be placed are left out.
+                    if g.read(s, cache).is_some() {
+                        self.add_geometry(name, g);
+                    }
                 }
                 _ => {
-                    panic!("Try to import unknown object type.");
+                    self.build_tree();
+                    return Err(ReadError::UnsupportedObject { object_type, name });
                 }
             }
         }
+        self.build_tree();
         let copy_geoes_count = s.read(&0u16);
         if copy_geoes_count > 1 {
-            panic!("Copy geometries are not supported yet.");
+            return Err(ReadError::UnsupportedCopyGeometries(copy_geoes_count));
         }
+        Ok(())
+    }
+
+    /// The geometry is not hit until the next `build_tree`.
+    pub fn add_geometry(&mut self, name: String, g: BasicGeometry) {
+        self.gm.insert(name, self.gs.len());
+        self.gs.push(Arc::new(g));
+    }
+
+    pub fn build_tree(&mut self) {
+        self.gt = Bvh::new(self.gs.clone(), GEOMETRIES_LEAF_SIZE);
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene::new()
     }
+}
 
-    // color,
-    fn hit(&self, r: &Ray3) -> Option<Vec3> {
+impl Hitable for Scene {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        self.gt.hit(r, t_min, t_max)
+    }
 
-        // TODO search by kdtree for geometries
-        for g in self.gs.iter() {
+    fn bounding_box(&self) -> Option<AABBox3> {
+        self.gt.bounding_box()
+    }
 
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Light>>) {
+        lights.extend(self.lights.iter().cloned());
+        self.gt.collect_emitters(lights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Read;
+
+    use super::super::super::math::vector::Vec3;
+    use super::super::light::{SunLight, SUN_ANGULAR_DIAMETER};
+    use super::*;
+
+    fn write_string(bytes: &mut Vec<u8>, s: &str) {
+        bytes.extend_from_slice(&(s.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(s.as_bytes());
+    }
+
+    /// Little endian scene file holding `objects`, each a type, a name and
+    /// its payload, read into a new scene.
+    fn read_objects(objects: &[(u8, &str, Vec<u8>)]) -> (Scene, Result<(), ReadError>) {
+        let mut bytes = vec![1u8];
+        bytes.extend_from_slice(&(objects.len() as u16).to_le_bytes());
+        for (object_type, name, _) in objects {
+            bytes.push(*object_type);
+            write_string(&mut bytes, name);
+            bytes.extend_from_slice(&0u32.to_le_bytes());
+        }
+        for (object_type, name, payload) in objects {
+            bytes.push(*object_type);
+            write_string(&mut bytes, name);
+            bytes.extend_from_slice(payload);
+        }
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+
+        let path = std::env::temp_dir().join(format!(
+            "dust-scene-{}-{}.gx3d",
+            std::process::id(),
+            objects.len()
+        ));
+        fs::write(&path, &bytes).unwrap();
+        let mut s = Stream::new(&path.to_string_lossy().into_owned());
+        let mut scene = Scene::new();
+        let result = scene.read(&mut s, None);
+        let mut rest = Vec::new();
+        s.reader.read_to_end(&mut rest).unwrap();
+        fs::remove_file(&path).unwrap();
+        if result.is_ok() {
+            assert!(rest.is_empty());
         }
-        None
+        (scene, result)
+    }
+
+    #[test]
+    fn added_lights_reach_the_renderer() {
+        let mut scene = Scene::new();
+        let toward = Vec3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        };
+        let color = Vec3 {
+            x: 1.0,
+            y: 0.9,
+            z: 0.8,
+        };
+        scene.lights.push(Arc::new(SunLight::new(
+            &toward,
+            color,
+            SUN_ANGULAR_DIAMETER,
+        )));
+        let mut lights = Vec::new();
+        scene.collect_emitters(&mut lights);
+        assert_eq!(lights.len(), 1);
+    }
+
+    #[test]
+    fn cameras_and_suns_stop_the_import() {
+        let (_, result) = read_objects(&[(4, "Camera", vec![1, 2, 3])]);
+        assert_eq!(
+            result,
+            Err(ReadError::UnsupportedObject {
+                object_type: 4,
+                name: "Camera".to_string(),
+            })
+        );
+        let (_, result) = read_objects(&[(5, "Sun", vec![1, 2, 3])]);
+        assert_eq!(
+            result,
+            Err(ReadError::UnsupportedObject {
+                object_type: 5,
+                name: "Sun".to_string(),
+            })
+        );
     }
 }
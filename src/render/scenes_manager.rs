@@ -1,23 +1,34 @@
 use std::collections::HashMap;
-use std::io::{
-    Seek,
-    SeekFrom,
-};
+use std::io::{Seek, SeekFrom};
 
-use ::io::file::Stream;
-use ::render::scene::Scene;
+use super::super::io::cache::Cache;
+use super::super::io::file::Stream;
+use super::scene::{ReadError, Scene};
 
 pub struct ScenesManager {
     name_index: HashMap<String, u64>,
+    /// Where the trees of loaded meshes are kept between launches.
+    cache: Option<Cache>,
 }
 
 impl ScenesManager {
+    /// Trees are cached in `Cache::get_default_directory`.
     pub fn new() -> ScenesManager {
         ScenesManager {
             name_index: HashMap::new(),
+            cache: Some(Cache::new(Cache::get_default_directory())),
         }
     }
 
+    pub fn get_cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
+    /// Moves the cache elsewhere, or turns it off with none.
+    pub fn set_cache(&mut self, cache: Option<Cache>) {
+        self.cache = cache;
+    }
+
     pub fn read_table(&mut self, s: &mut Stream) {
         let scene_count = s.read(&0u32) as usize;
         println!("Scene count is: {:?}", scene_count);
@@ -29,10 +40,40 @@ impl ScenesManager {
         }
     }
 
-    pub fn get_scene(&self, name: &String, s: &mut Stream) -> Scene {
-        s.reader.seek(SeekFrom::Start(self.name_index[name]));
+    pub fn get_scene(&self, name: &str, s: &mut Stream) -> Result<Scene, ReadError> {
+        s.reader
+            .seek(SeekFrom::Start(self.name_index[name]))
+            .expect("Unable to seek to the scene.");
         let mut scene = Scene::new();
-        scene.read(s);
-        scene
+        scene.read(s, self.cache.as_ref())?;
+        Ok(scene)
+    }
+}
+
+impl Default for ScenesManager {
+    fn default() -> Self {
+        ScenesManager::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::super::super::io::cache::DIRECTORY_VARIABLE;
+    use super::*;
+
+    #[test]
+    fn trees_are_cached_where_the_variable_says() {
+        let directory = env::temp_dir().join(format!("dust-configured-{}", std::process::id()));
+        env::set_var(DIRECTORY_VARIABLE, &directory);
+        let configured = ScenesManager::new();
+        env::remove_var(DIRECTORY_VARIABLE);
+        let fallback = ScenesManager::new();
+        assert_eq!(configured.get_cache().unwrap().get_directory(), &directory);
+        assert_eq!(
+            fallback.get_cache().unwrap().get_directory(),
+            &env::temp_dir().join("dust-cache")
+        );
     }
 }
@@ -0,0 +1,159 @@
+use std::path::Path;
+
+use image::{self, ColorType};
+
+use super::super::math::vector::{Vec2, Vec3};
+use super::super::math::Real;
+
+/// A color that changes over a surface.
+pub trait Texture: Sync + Send {
+    /// Color at the texture coordinates `uv` of the point `p`.
+    fn value(&self, uv: &Vec2, p: &Vec3) -> Vec3;
+}
+
+/// Linear color of an sRGB encoded channel in [0, 1].
+fn srgb_to_linear(c: Real) -> Real {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// An image looked up by the texture coordinates, which repeat outside of
+/// [0, 1] and have v growing from the bottom row up.
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    /// Linear colors row by row from the top.
+    pixels: Vec<Vec3>,
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3>) -> Self {
+        if width == 0 || height == 0 || pixels.len() != width * height {
+            panic!("Image size does not match its pixels.");
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Floating point images like HDR and EXR ones are taken as linear,
+    /// the others as sRGB encoded.
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        let img = image::open(path).expect("Unable to load the image.");
+        let linear = matches!(img.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+        let img = img.into_rgb32f();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let decode = |c: f32| {
+            let c = c as Real;
+            if linear {
+                c
+            } else {
+                srgb_to_linear(c)
+            }
+        };
+        let pixels = img
+            .pixels()
+            .map(|p| Vec3 {
+                x: decode(p[0]),
+                y: decode(p[1]),
+                z: decode(p[2]),
+            })
+            .collect();
+        Self::new(width, height, pixels)
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    /// Pixel at column `x` and row `y` from the top.
+    pub fn get_pixel(&self, x: usize, y: usize) -> &Vec3 {
+        &self.pixels[y * self.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, uv: &Vec2, _p: &Vec3) -> Vec3 {
+        let u = uv.x - uv.x.floor();
+        let v = uv.y - uv.y.floor();
+        let x = ((u * self.width as Real) as usize).min(self.width - 1);
+        let y = (((1.0 - v) * self.height as Real) as usize).min(self.height - 1);
+        *self.get_pixel(x, y)
+    }
+}
+
+/// Six square images around the origin, in the order +x, -x, +y, -y, +z
+/// and -z, each one seen from inside the cube the way OpenGL lays them.
+pub struct CubeMap {
+    faces: [ImageTexture; 6],
+}
+
+impl CubeMap {
+    pub fn new(faces: [ImageTexture; 6]) -> Self {
+        let size = faces[0].get_width();
+        if faces
+            .iter()
+            .any(|f| f.get_width() != size || f.get_height() != size)
+        {
+            panic!("Cube map faces must be squares of the same size.");
+        }
+        Self { faces }
+    }
+
+    pub fn open<P: AsRef<Path>>(paths: &[P; 6]) -> Self {
+        Self::new([
+            ImageTexture::open(&paths[0]),
+            ImageTexture::open(&paths[1]),
+            ImageTexture::open(&paths[2]),
+            ImageTexture::open(&paths[3]),
+            ImageTexture::open(&paths[4]),
+            ImageTexture::open(&paths[5]),
+        ])
+    }
+
+    /// Width and height of every face.
+    pub fn get_size(&self) -> usize {
+        self.faces[0].get_width()
+    }
+
+    /// Color seen along the direction `d`, which does not need to be unit.
+    pub fn value(&self, d: &Vec3) -> Vec3 {
+        let a = d.abs();
+        // The face of the major axis and the coordinates on it, s to the
+        // right and t down the image.
+        let (face, sc, tc, ma) = if a.x >= a.y && a.x >= a.z {
+            if d.x > 0.0 {
+                (0, -d.z, -d.y, a.x)
+            } else {
+                (1, d.z, -d.y, a.x)
+            }
+        } else if a.y >= a.z {
+            if d.y > 0.0 {
+                (2, d.x, d.z, a.y)
+            } else {
+                (3, d.x, -d.z, a.y)
+            }
+        } else if d.z > 0.0 {
+            (4, d.x, -d.y, a.z)
+        } else {
+            (5, -d.x, -d.y, a.z)
+        };
+        if ma <= 0.0 {
+            return Vec3::new();
+        }
+        let f = &self.faces[face];
+        let size = f.get_width();
+        let pixel =
+            |c: Real| ((0.5 * (c / ma + 1.0) * size as Real).max(0.0) as usize).min(size - 1);
+        *f.get_pixel(pixel(sc), pixel(tc))
+    }
+}
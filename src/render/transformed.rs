@@ -0,0 +1,247 @@
+use std::sync::Arc;
+
+use super::super::math::aabbox::{AABBox3, ExpandableToOther};
+use super::super::math::matrix::Mat4x4;
+use super::super::math::quaternion::Quaternion;
+use super::super::math::ray::Ray3;
+use super::super::math::vector::{Vec2, Vec3};
+use super::super::math::Real;
+use super::hit::{Hitable, Info as HitInfo};
+use super::light::{Light, LightSample};
+
+/// Moments the bounds of a moving object are sampled at.
+const MOTION_BOUNDS_STEPS: usize = 16;
+
+/// Two transforms and the time interval an object moves from the first to
+/// the second in, kept decomposed so they can be interpolated.
+struct Motion {
+    time0: Real,
+    time1: Real,
+    start: (Vec3, Quaternion, Mat4x4),
+    end: (Vec3, Quaternion, Mat4x4),
+}
+
+impl Motion {
+    /// Translation and scale are interpolated linearly and rotation
+    /// spherically, which keeps a rotating object rigid in between.
+    fn get_transform(&self, time: Real) -> Mat4x4 {
+        let s = if self.time1 > self.time0 {
+            ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let translation = &self.start.0 + &(&(&self.end.0 - &self.start.0) * s);
+        let rotation = self.start.1.slerp(&self.end.1, s);
+        let mut rest = self.start.2;
+        for i in 0..4 {
+            for j in 0..4 {
+                rest.data[i][j] += (self.end.2.data[i][j] - self.start.2.data[i][j]) * s;
+            }
+        }
+        Mat4x4::translation(&translation) * rotation.to_mat4x4() * rest
+    }
+}
+
+/// Places any `Hitable` in the world by a matrix, rays are moved into the
+/// object's space and hits are moved back.
+pub struct Transformed<T: Hitable> {
+    pub object: T,
+    transform: Mat4x4,
+    inverse_transform: Mat4x4,
+    /// Only for objects that move while the shutter is open.
+    motion: Option<Motion>,
+}
+
+impl<T: Hitable> Transformed<T> {
+    /// None when `transform` is not invertible.
+    pub fn new(object: T, transform: Mat4x4) -> Option<Self> {
+        Some(Transformed {
+            object,
+            inverse_transform: transform.inverse()?,
+            transform,
+            motion: None,
+        })
+    }
+
+    /// An object placed by `start` at `time0` that moves to `end` at
+    /// `time1`, it stays at those ends outside of the interval. None when
+    /// `start` is not invertible, other moments that are not are missed by
+    /// every ray.
+    pub fn moving(object: T, start: Mat4x4, end: Mat4x4, time0: Real, time1: Real) -> Option<Self> {
+        let mut t = Self::new(object, start)?;
+        t.motion = Some(Motion {
+            time0,
+            time1,
+            start: start.decompose(),
+            end: end.decompose(),
+        });
+        Some(t)
+    }
+
+    /// The transform at `time0` for moving objects.
+    pub fn get_transform(&self) -> &Mat4x4 {
+        &self.transform
+    }
+
+    pub fn get_transform_at(&self, time: Real) -> Mat4x4 {
+        match self.motion {
+            Some(ref motion) => motion.get_transform(time),
+            None => self.transform,
+        }
+    }
+
+    /// Places the object statically, it stops moving if it did. Returns
+    /// false and keeps the placement when `transform` is not invertible.
+    pub fn set_transform(&mut self, transform: Mat4x4) -> bool {
+        match transform.inverse() {
+            Some(inverse_transform) => {
+                self.inverse_transform = inverse_transform;
+                self.transform = transform;
+                self.motion = None;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<T: Hitable> Hitable for Transformed<T> {
+    fn hit(&self, r: &Ray3, t_min: Real, t_max: Real) -> Option<HitInfo<'_>> {
+        let (transform, inverse_transform) = match self.motion {
+            Some(ref motion) => {
+                let transform = motion.get_transform(r.time);
+                (transform, transform.inverse()?)
+            }
+            None => (self.transform, self.inverse_transform),
+        };
+        let (local_ray, t_error) = inverse_transform.transform_ray_with_error(r);
+        self.object
+            .hit(&local_ray, t_min.max(t_error), t_max)
+            .map(|rec| rec.into_world(&transform, &inverse_transform))
+    }
+
+    fn bounding_box(&self) -> Option<AABBox3> {
+        let b = self.object.bounding_box()?;
+        let motion = match self.motion {
+            Some(ref motion) => motion,
+            None => return Some(b.transformed(&self.transform)),
+        };
+        let mut result = AABBox3::new();
+        let duration = motion.time1 - motion.time0;
+        for i in 0..=MOTION_BOUNDS_STEPS {
+            let time = motion.time0 + duration * i as Real / MOTION_BOUNDS_STEPS as Real;
+            result.expand(&b.transformed(&motion.get_transform(time)));
+        }
+        // Between two samples a rotating corner leaves the straight line
+        // joining its sampled places by at most the sagitta of its arc.
+        let angle = 2.0 * motion.start.1.dot(&motion.end.1).abs().min(1.0).acos();
+        let step = angle / MOTION_BOUNDS_STEPS as Real;
+        let reach = |rest: &Mat4x4| {
+            let scaled = b.transformed(rest);
+            scaled.get_center().length() + 0.5 * scaled.get_diagonal().length()
+        };
+        let radius = reach(&motion.start.2).max(reach(&motion.end.2));
+        let pad = radius * (1.0 - (step * 0.5).cos());
+        let pad = Vec3 {
+            x: pad,
+            y: pad,
+            z: pad,
+        };
+        result.blf -= &pad;
+        result.trr += &pad;
+        Some(result)
+    }
+
+    /// Lights have no time to place a moving object at, so only rays that
+    /// hit moving objects find their light.
+    fn collect_emitters(&self, lights: &mut Vec<Arc<dyn Light>>) {
+        if self.motion.is_some() {
+            return;
+        }
+        let mut local = Vec::new();
+        self.object.collect_emitters(&mut local);
+        let axis = |x, y, z| self.transform.transform_direction(&Vec3 { x, y, z });
+        let determinant = axis(1.0, 0.0, 0.0).dot(&axis(0.0, 1.0, 0.0).cross(&axis(0.0, 0.0, 1.0)));
+        for light in local {
+            lights.push(Arc::new(TransformedLight {
+                light,
+                transform: self.transform,
+                inverse_transform: self.inverse_transform,
+                determinant: determinant.abs(),
+            }));
+        }
+    }
+}
+
+/// A light of an object placed by `Transformed`, sampled in the object's
+/// space. Scaling stretches solid angles unevenly, so densities per
+/// steradian are carried over by the Jacobian of the direction mapping.
+struct TransformedLight {
+    light: Arc<dyn Light>,
+    transform: Mat4x4,
+    inverse_transform: Mat4x4,
+    /// Volume scale of `transform`.
+    determinant: Real,
+}
+
+impl TransformedLight {
+    fn to_local<'a>(&self, rec: &HitInfo<'a>) -> HitInfo<'a> {
+        HitInfo {
+            t: rec.t,
+            p: rec.p,
+            p_error: rec.p_error,
+            n: rec.n,
+            ng: rec.ng,
+            uv: rec.uv,
+            m: rec.m,
+        }
+        .into_world(&self.inverse_transform, &self.transform)
+    }
+
+    /// returns: (the unit direction of the object's space for the unit
+    /// direction `w` of the world, the factor that turns densities of the
+    /// first into ones of the second)
+    fn to_local_direction(&self, w: &Vec3) -> (Vec3, Real) {
+        let l = self.inverse_transform.transform_direction(w);
+        let length = l.length();
+        (
+            &l / length,
+            1.0 / (self.determinant * length * length * length),
+        )
+    }
+}
+
+impl Light for TransformedLight {
+    fn sample_li(&self, rec: &HitInfo, u: &Vec2) -> Option<LightSample> {
+        let s = self.light.sample_li(&self.to_local(rec), u)?;
+        let w = self.transform.transform_direction(&s.wi);
+        let length = w.length();
+        if length <= 0.0 {
+            return None;
+        }
+        let pdf = if self.light.is_delta() {
+            s.pdf
+        } else {
+            s.pdf * length * length * length / self.determinant
+        };
+        Some(LightSample {
+            wi: &w / length,
+            li: s.li,
+            pdf,
+            shadow: self.transform.transform_ray(&s.shadow),
+        })
+    }
+
+    fn pdf_li(&self, rec: &HitInfo, wi: &Vec3) -> Real {
+        let (d, factor) = self.to_local_direction(wi);
+        self.light.pdf_li(&self.to_local(rec), &d) * factor
+    }
+
+    fn le(&self, d: &Vec3) -> Vec3 {
+        self.light.le(&self.to_local_direction(d).0)
+    }
+
+    fn is_delta(&self) -> bool {
+        self.light.is_delta()
+    }
+}
@@ -1,5 +1,5 @@
+use super::super::io::file::Stream;
 use super::super::math::vector::{Vec2, Vec3};
-// use ::io::file::Stream;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Vertex {
@@ -9,13 +9,27 @@ pub struct Vertex {
 }
 
 impl Vertex {
-    // pub fn read(&mut self, s: &mut Stream, has_nr: bool, has_texture_coordinate: bool) {
-    //     self.ps.read(s);
-    //     if has_nr {
-    //         self.nr.read(s);
-    //     }
-    //     if has_texture_coordinate {
-    //         self.uv.read(s);
-    //     }
-    // }
+    pub fn new() -> Self {
+        Vertex {
+            ps: Vec3::new(),
+            nr: Vec3::new(),
+            uv: Vec2::new(),
+        }
+    }
+
+    pub fn read(&mut self, s: &mut Stream, has_nr: bool, has_texture_coordinate: bool) {
+        self.ps.read(s);
+        if has_nr {
+            self.nr.read(s);
+        }
+        if has_texture_coordinate {
+            self.uv.read(s);
+        }
+    }
+}
+
+impl Default for Vertex {
+    fn default() -> Self {
+        Vertex::new()
+    }
 }
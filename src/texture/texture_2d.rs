@@ -1,2 +0,0 @@
-
-pub struct Texture2D {}